@@ -0,0 +1,185 @@
+use crate::{encoding, hashing::checksum, hashing::hash160};
+use secp256k1::PublicKey;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+// Base58Check with this version byte gives addresses starting with "M".
+const ADDRESS_VERSION: u8 = 0x32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(pub [u8; 20]);
+
+#[derive(Debug, PartialEq)]
+pub enum AddressError {
+    InvalidCharacter { character: char, index: usize },
+    InvalidLength,
+    WrongPrefix,
+    BadChecksum,
+}
+
+impl From<&PublicKey> for Address {
+    fn from(public_key: &PublicKey) -> Self {
+        Address(hash160(&public_key.serialize()))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut data = vec![ADDRESS_VERSION];
+        data.extend_from_slice(&self.0);
+        data.extend_from_slice(&checksum(&data));
+
+        write!(f, "{}", bs58::encode(data).into_string())
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Address({})", self)
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = bs58::decode(s).into_vec().map_err(|e| match e {
+            bs58::decode::Error::InvalidCharacter { character, index } => {
+                AddressError::InvalidCharacter { character, index }
+            }
+            bs58::decode::Error::NonAsciiCharacter { index } => AddressError::InvalidCharacter {
+                character: s[index..].chars().next().unwrap_or_default(),
+                index,
+            },
+            _ => AddressError::InvalidLength,
+        })?;
+
+        if data.len() != 25 {
+            return Err(AddressError::InvalidLength);
+        }
+
+        if checksum(&data[..21]) != data[21..] {
+            return Err(AddressError::BadChecksum);
+        }
+
+        if data[0] != ADDRESS_VERSION {
+            return Err(AddressError::WrongPrefix);
+        }
+
+        let mut hash = [0; 20];
+        hash.copy_from_slice(&data[1..21]);
+
+        Ok(Address(hash))
+    }
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::InvalidCharacter { character, index } => {
+                write!(f, "invalid character {:?} at position {}", character, index)
+            }
+            AddressError::InvalidLength => write!(f, "address has a wrong length"),
+            AddressError::WrongPrefix => write!(f, "address is not a myox address"),
+            AddressError::BadChecksum => write!(f, "address checksum does not match"),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        if s.is_human_readable() {
+            s.collect_str(self)
+        } else {
+            s.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        if d.is_human_readable() {
+            let address = String::deserialize(d)?;
+
+            address.parse().map_err(de::Error::custom)
+        } else {
+            let bytes = d.deserialize_bytes(encoding::BytesVisitor)?;
+
+            if bytes.len() != 20 {
+                return Err(de::Error::invalid_length(bytes.len(), &"20 bytes"));
+            }
+
+            let mut hash = [0; 20];
+            hash.copy_from_slice(&bytes);
+
+            Ok(Address(hash))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use secp256k1::{Secp256k1, SecretKey};
+
+    fn test_address() -> Address {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+
+        Address::from(&PublicKey::from_secret_key(&Secp256k1::new(), &secret_key))
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let address = test_address();
+        let encoded = address.to_string();
+
+        assert!(encoded.starts_with('M'));
+        assert_eq!(encoded.parse::<Address>(), Ok(address));
+        assert_eq!(
+            serde_json::to_string(&address).unwrap(),
+            format!("\"{}\"", encoded)
+        );
+        assert_eq!(
+            bincode::deserialize::<Address>(&bincode::serialize(&address).unwrap()).unwrap(),
+            address
+        );
+    }
+
+    #[test]
+    fn test_typos() {
+        let encoded = test_address().to_string();
+
+        let last = if encoded.ends_with('2') { '3' } else { '2' };
+        let mistyped = format!("{}{}", &encoded[..encoded.len() - 1], last);
+        assert_eq!(mistyped.parse::<Address>(), Err(AddressError::BadChecksum));
+
+        let replaced = format!("{}0{}", &encoded[..3], &encoded[4..]);
+        assert_eq!(
+            replaced.parse::<Address>(),
+            Err(AddressError::InvalidCharacter {
+                character: '0',
+                index: 3
+            })
+        );
+
+        assert_eq!(
+            encoded[1..].parse::<Address>(),
+            Err(AddressError::InvalidLength)
+        );
+        assert!(serde_json::from_str::<Address>("\"foo\"").is_err());
+    }
+
+    #[test]
+    fn test_wrong_prefix() {
+        let mut data = vec![0];
+        data.extend_from_slice(&test_address().0);
+        data.extend_from_slice(&checksum(&data));
+
+        assert_eq!(
+            bs58::encode(data).into_string().parse::<Address>(),
+            Err(AddressError::WrongPrefix)
+        );
+    }
+}
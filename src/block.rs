@@ -1,19 +1,101 @@
 use crate::{
+    address::Address,
     blockchain::Blockchain,
-    hashing::{gen_hash, to_binary},
+    hashing::{gen_legacy_hash, BlockHash, LEGACY_GENESIS_HASH, LEGACY_GENESIS_PREV_HASH},
+    transaction::Transaction,
     utils::time_now,
 };
 
-use chrono::Utc;
 use proptest::prelude::*;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const HEADER_VERSION: u8 = 1;
+
+#[derive(PartialEq, Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum BlockData {
+    Transactions(Vec<String>),
+    Legacy(String),
+}
+
+impl BlockData {
+    pub fn legacy_hash_input(&self) -> String {
+        match self {
+            BlockData::Transactions(transactions) => json!(transactions).to_string(),
+            BlockData::Legacy(data) => data.to_string(),
+        }
+    }
+
+    fn encode(&self, header: &mut Vec<u8>) {
+        match self {
+            BlockData::Transactions(transactions) => {
+                header.push(1);
+                header.extend_from_slice(&(transactions.len() as u64).to_be_bytes());
+                for transaction in transactions {
+                    encode_bytes(header, transaction.as_bytes());
+                }
+            }
+            BlockData::Legacy(data) => {
+                header.push(0);
+                encode_bytes(header, data.as_bytes());
+            }
+        }
+    }
+
+    pub fn transactions(&self) -> serde_json::Result<Vec<Transaction>> {
+        match self {
+            BlockData::Transactions(transactions) => transactions
+                .iter()
+                .map(|transaction| serde_json::from_str(transaction))
+                .collect(),
+            BlockData::Legacy(_) => Ok(vec![]),
+        }
+    }
+}
+
+fn encode_bytes(header: &mut Vec<u8>, bytes: &[u8]) {
+    header.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+    header.extend_from_slice(bytes);
+}
+
+impl From<String> for BlockData {
+    fn from(data: String) -> Self {
+        BlockData::Legacy(data)
+    }
+}
+
+impl From<&str> for BlockData {
+    fn from(data: &str) -> Self {
+        BlockData::Legacy(data.to_string())
+    }
+}
+
+impl From<&[Transaction]> for BlockData {
+    fn from(transactions: &[Transaction]) -> Self {
+        BlockData::Transactions(
+            transactions
+                .iter()
+                .map(|transaction| {
+                    serde_json::to_string(transaction).expect("transaction serialized")
+                })
+                .collect(),
+        )
+    }
+}
+
+impl From<Vec<Transaction>> for BlockData {
+    fn from(transactions: Vec<Transaction>) -> Self {
+        BlockData::from(&transactions[..])
+    }
+}
 
 #[derive(PartialEq, Debug, Clone, Deserialize, Serialize)]
 pub struct Block {
     pub timestamp: i64,
-    pub data: String,
-    pub hash: String,
-    pub prev_hash: String,
+    pub data: BlockData,
+    pub hash: BlockHash,
+    pub prev_hash: BlockHash,
     pub difficulty: usize,
     pub nonce: usize,
 }
@@ -21,53 +103,110 @@ pub struct Block {
 impl Block {
     pub fn new_with_previous<T>(data: T, prev_block: &Block) -> Self
     where
-        T: AsRef<str>,
+        T: Into<BlockData>,
     {
-        let mut date_now = time_now();
+        let data = data.into();
+        // A block must be newer than its parent even when both are mined
+        // within the same millisecond.
+        let now = || time_now().max(prev_block.timestamp + 1);
+        let mut date_now = now();
         let mut nonce = 0;
         let mut difficulty =
-            Blockchain::optimize_difficulty(&prev_block, date_now - prev_block.timestamp);
-        let mut hash = gen_hash(vec![
-            date_now.to_string(),
-            (*data.as_ref()).to_string(),
-            prev_block.hash.to_string(),
-            difficulty.to_string(),
-            nonce.to_string(),
-        ]);
-
-        while hash[0..difficulty] != "0".repeat(difficulty) {
+            Blockchain::optimize_difficulty(prev_block, date_now - prev_block.timestamp);
+        let mut hash = Block::compute_hash(date_now, &data, &prev_block.hash, difficulty, nonce);
+
+        while !hash.meets_difficulty(difficulty) {
             nonce += 1;
-            date_now = time_now();
+            date_now = now();
             difficulty =
-                Blockchain::optimize_difficulty(&prev_block, date_now - prev_block.timestamp);
-            // println!("{}", difficulty);
-            // println!("{}", hash);
-            // println!("{}", hash);
-            hash = gen_hash(vec![
-                date_now.to_string(),
-                (*data.as_ref()).to_string(),
-                prev_block.hash.to_string(),
-                difficulty.to_string(),
-                nonce.to_string(),
-            ]);
+                Blockchain::optimize_difficulty(prev_block, date_now - prev_block.timestamp);
+            hash = Block::compute_hash(date_now, &data, &prev_block.hash, difficulty, nonce);
         }
 
         Self {
             timestamp: date_now,
-            data: (*data.as_ref()).to_string(),
+            data,
             hash,
-            prev_hash: prev_block.hash.to_string(),
+            prev_hash: prev_block.hash,
             difficulty,
             nonce,
         }
     }
 
+    pub fn seal(timestamp: i64, data: BlockData, prev_block: &Block, difficulty: usize) -> Self {
+        for nonce in 0.. {
+            let hash = Block::compute_hash(timestamp, &data, &prev_block.hash, difficulty, nonce);
+
+            if hash.meets_difficulty(difficulty) {
+                return Self {
+                    timestamp,
+                    data,
+                    hash,
+                    prev_hash: prev_block.hash,
+                    difficulty,
+                    nonce,
+                };
+            }
+        }
+
+        unreachable!()
+    }
+
+    pub fn legacy_hash(&self, legacy_prev_hash: String) -> BlockHash {
+        gen_legacy_hash(vec![
+            self.timestamp.to_string(),
+            self.data.legacy_hash_input(),
+            legacy_prev_hash,
+            self.difficulty.to_string(),
+            self.nonce.to_string(),
+        ])
+    }
+
+    pub fn header(
+        timestamp: i64,
+        data: &BlockData,
+        prev_hash: &BlockHash,
+        difficulty: usize,
+        nonce: usize,
+    ) -> Vec<u8> {
+        let mut header = vec![HEADER_VERSION];
+        header.extend_from_slice(&timestamp.to_be_bytes());
+        data.encode(&mut header);
+        header.extend_from_slice(&prev_hash.0);
+        header.extend_from_slice(&(difficulty as u64).to_be_bytes());
+        header.extend_from_slice(&(nonce as u64).to_be_bytes());
+
+        header
+    }
+
+    pub fn compute_hash(
+        timestamp: i64,
+        data: &BlockData,
+        prev_hash: &BlockHash,
+        difficulty: usize,
+        nonce: usize,
+    ) -> BlockHash {
+        BlockHash::digest(&Block::header(
+            timestamp, data, prev_hash, difficulty, nonce,
+        ))
+    }
+
+    pub fn new_with_reward(
+        mut transactions: Vec<Transaction>,
+        miner: Address,
+        prev_block: &Block,
+    ) -> Self {
+        transactions.push(Transaction::reward(miner));
+
+        Block::new_with_previous(transactions, prev_block)
+    }
+
     pub fn get_first_block() -> Self {
         Self {
             timestamp: 0,
-            data: "empty_data".to_string(),
-            hash: "empty_hash".to_string(),
-            prev_hash: "empty_previous_hash".to_string(),
+            data: "empty_data".into(),
+            hash: BlockHash::from_legacy(LEGACY_GENESIS_HASH).unwrap(),
+            prev_hash: BlockHash::from_legacy(LEGACY_GENESIS_PREV_HASH).unwrap(),
             difficulty: 3,
             nonce: 0,
         }
@@ -78,15 +217,125 @@ proptest! {
     #[test]
     fn test_block_creation(data in ".*") {
         let block = Block::new_with_previous(data.clone(), &Block::get_first_block());
-        let hash = gen_hash(vec![
-            block.timestamp.to_string(),
-            data.to_owned(),
-            Block::get_first_block().hash.to_owned(),
-            block.difficulty.to_string(),
-            block.nonce.to_string(),
-        ]);
-        assert_eq!(block.data, data);
+        let hash = Block::compute_hash(
+            block.timestamp,
+            &BlockData::Legacy(data.clone()),
+            &Block::get_first_block().hash,
+            block.difficulty,
+            block.nonce,
+        );
+        assert_eq!(block.data, BlockData::Legacy(data));
         assert_eq!(block.hash, hash);
-        assert_eq!(block.prev_hash, Block::get_first_block().hash.to_owned());
+        assert_eq!(block.prev_hash, Block::get_first_block().hash);
+        assert!(block.hash.meets_difficulty(block.difficulty));
+    }
+
+    #[test]
+    fn test_header_injective(
+        a in (any::<i64>(), ".*", 0..256usize, any::<usize>()),
+        b in (any::<i64>(), ".*", 0..256usize, any::<usize>()),
+    ) {
+        prop_assume!(a != b);
+        let prev_hash = Block::get_first_block().hash;
+
+        assert_ne!(
+            Block::header(a.0, &a.1.into(), &prev_hash, a.2, a.3),
+            Block::header(b.0, &b.1.into(), &prev_hash, b.2, b.3)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{transaction::mining_reward, wallet::Wallet};
+
+    #[test]
+    fn test_block_with_transactions() {
+        let tx = Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap();
+        let block = Block::new_with_previous(vec![tx.clone()], &Block::get_first_block());
+
+        let transactions = block.data.transactions().unwrap();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].id, tx.id);
+        assert_eq!(transactions[0].input, tx.input);
+        assert_eq!(transactions[0].output_map, tx.output_map);
+    }
+
+    #[test]
+    fn test_block_with_reward() {
+        let miner = Wallet::new(0).address();
+        let tx = Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap();
+        let block = Block::new_with_reward(vec![tx], miner, &Block::get_first_block());
+
+        let transactions = block.data.transactions().unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions.iter().filter(|tx| tx.is_reward()).count(), 1);
+        assert_eq!(
+            *transactions.last().unwrap().output_map.get(&miner).unwrap(),
+            mining_reward()
+        );
+    }
+
+    #[test]
+    fn test_header_field_boundaries() {
+        let prev_hash = Block::get_first_block().hash;
+        let legacy = |parts: [&str; 4]| {
+            gen_legacy_hash(vec![
+                parts[0].to_string(),
+                parts[1].to_string(),
+                prev_hash.to_string(),
+                parts[2].to_string(),
+                parts[3].to_string(),
+            ])
+        };
+
+        assert_eq!(legacy(["12", "3", "4", "0"]), legacy(["1", "23", "4", "0"]));
+        assert_ne!(
+            Block::compute_hash(12, &"3".into(), &prev_hash, 4, 0),
+            Block::compute_hash(1, &"23".into(), &prev_hash, 4, 0)
+        );
+
+        assert_eq!(legacy(["1", "a", "12", "3"]), legacy(["1", "a", "1", "23"]));
+        assert_ne!(
+            Block::compute_hash(1, &"a".into(), &prev_hash, 12, 3),
+            Block::compute_hash(1, &"a".into(), &prev_hash, 1, 23)
+        );
+    }
+
+    #[test]
+    fn test_header_data_variants() {
+        let prev_hash = Block::get_first_block().hash;
+        let transactions = BlockData::Transactions(vec!["a".to_string(), "b".to_string()]);
+        let merged = BlockData::Transactions(vec!["ab".to_string()]);
+        let legacy = BlockData::Legacy(transactions.legacy_hash_input());
+
+        assert_ne!(
+            Block::compute_hash(1, &transactions, &prev_hash, 4, 0),
+            Block::compute_hash(1, &merged, &prev_hash, 4, 0)
+        );
+        assert_ne!(
+            Block::compute_hash(1, &transactions, &prev_hash, 4, 0),
+            Block::compute_hash(1, &legacy, &prev_hash, 4, 0)
+        );
+    }
+
+    #[test]
+    fn test_seal() {
+        let genesis = Block::get_first_block();
+        let block = Block::seal(1, "foo".into(), &genesis, 8);
+
+        assert_eq!(block.timestamp, 1);
+        assert_eq!(block.prev_hash, genesis.hash);
+        assert!(block.hash.meets_difficulty(8));
+    }
+
+    #[test]
+    fn test_legacy_data_deserialized() {
+        let block = Block::new_with_previous("foo", &Block::get_first_block());
+        let json = serde_json::to_string(&block).unwrap();
+
+        assert!(json.contains(r#""data":"foo""#));
+        assert_eq!(serde_json::from_str::<Block>(&json).unwrap(), block);
     }
 }
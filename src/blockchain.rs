@@ -1,70 +1,374 @@
-use crate::block::Block;
-use crate::hashing::gen_hash;
+use crate::block::{Block, BlockData};
+use crate::error::Error;
+use crate::hashing::{BlockHash, LEGACY_GENESIS_HASH};
+use crate::storage::BlockStore;
+use crate::transaction::Transaction;
+use crate::utils::time_now;
+use crate::wallet::calculate_balance_in;
+#[cfg(test)]
+use crate::{address::Address, wallet::Wallet};
 
-use chrono::Utc;
-use proptest::prelude::*;
-use rand::Rng;
+use std::{collections::HashSet, fmt};
 
 const MINING_SPEED: usize = 1000;
+pub const MIN_DIFFICULTY: usize = 1;
+// How far ahead of the local clock a block's timestamp may be, in ms.
+const MAX_CLOCK_DRIFT: i64 = 2 * 60 * 1000;
+
+#[derive(Debug, PartialEq)]
+pub enum ReplaceChainResult {
+    Replaced,
+    Invalid(Error),
+    LessWork,
+    TieLost,
+    StoreFailed(Error),
+}
 
-#[derive(Debug)]
 pub struct Blockchain {
     pub chain: Vec<Block>,
+    store: Option<Box<dyn BlockStore + Send>>,
+}
+
+impl fmt::Debug for Blockchain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Blockchain")
+            .field("chain", &self.chain)
+            .finish()
+    }
 }
 
 impl Blockchain {
     pub fn new(blocks: Vec<Block>) -> Self {
-        Self { chain: blocks }
+        Self {
+            chain: blocks,
+            store: None,
+        }
     }
 
-    pub fn add_block(&mut self, data: String) {
-        self.chain
-            .push(Block::new_with_previous(data, self.chain.last().unwrap()))
+    pub fn open(mut store: Box<dyn BlockStore + Send>) -> Result<Self, Error> {
+        let mut blockchain = Blockchain::new(store.load()?);
+
+        if blockchain.is_legacy() {
+            blockchain = Blockchain::migrate_legacy(&blockchain)?;
+            store.truncate(1)?;
+            for block in &blockchain.chain[1..] {
+                store.append(block)?;
+            }
+        }
+
+        if blockchain.chain.is_empty() {
+            let genesis = Block::get_first_block();
+            store.append(&genesis)?;
+            blockchain.chain.push(genesis);
+        }
+
+        Blockchain::validate_chain(&blockchain)?;
+        blockchain.store = Some(store);
+
+        Ok(blockchain)
+    }
+
+    pub fn add_block<T>(&mut self, data: T) -> Result<(), Error>
+    where
+        T: Into<BlockData>,
+    {
+        self.push(Block::new_with_previous(data, self.chain.last().unwrap()))
+    }
+
+    #[cfg(test)]
+    pub fn add_block_with_reward(
+        &mut self,
+        transactions: Vec<Transaction>,
+        miner: Address,
+    ) -> Result<(), Error> {
+        self.push(Block::new_with_reward(
+            transactions,
+            miner,
+            self.chain.last().unwrap(),
+        ))
+    }
+
+    fn push(&mut self, block: Block) -> Result<(), Error> {
+        if let Some(store) = self.store.as_mut() {
+            store.append(&block)?;
+        }
+
+        self.chain.push(block);
+
+        Ok(())
     }
 
     pub fn get_nth_block(&self, i: usize) -> Option<&Block> {
         self.chain.get(i)
     }
 
-    pub fn is_valid_chain(chain: &Blockchain) -> bool {
-        if *chain.get_nth_block(0).unwrap() != Block::get_first_block() {
-            return false;
+    pub fn find_block(&self, id: &str) -> Option<&Block> {
+        match BlockHash::from_hex(id) {
+            Some(hash) => self.chain.iter().find(|block| block.hash == hash),
+            None => self.get_nth_block(id.parse().ok()?),
+        }
+    }
+
+    pub fn find_transaction(&self, id: &str) -> Option<(usize, Transaction)> {
+        self.chain.iter().enumerate().find_map(|(height, block)| {
+            block
+                .data
+                .transactions()
+                .unwrap_or_default()
+                .into_iter()
+                .find(|transaction| transaction.id == id)
+                .map(|transaction| (height, transaction))
+        })
+    }
+
+    pub fn page(&self, from: usize, limit: usize) -> &[Block] {
+        let from = from.min(self.chain.len());
+        let to = from.saturating_add(limit).min(self.chain.len());
+
+        &self.chain[from..to]
+    }
+
+    pub fn validate_chain(chain: &Blockchain) -> Result<(), Error> {
+        Blockchain::validate_from(chain, 0)
+    }
+
+    // Blocks before `from` are trusted, e.g. because they are shared with a
+    // chain that was already validated.
+    fn validate_from(chain: &Blockchain, from: usize) -> Result<(), Error> {
+        if from == 0 && chain.get_nth_block(0) != Some(&Block::get_first_block()) {
+            return Err(Error::InvalidGenesis);
+        }
+
+        for i in from.max(1)..chain.chain.len() {
+            Blockchain::validate_block(&chain.chain[..i], &chain.chain[i])?;
+        }
+
+        Ok(())
+    }
+
+    pub fn append(&mut self, block: Block) -> Result<(), Error> {
+        Blockchain::validate_block(&self.chain, &block)?;
+
+        self.push(block)
+    }
+
+    fn validate_block(history: &[Block], block: &Block) -> Result<(), Error> {
+        let index = history.len();
+        let prev_block = history.last().ok_or(Error::InvalidGenesis)?;
+
+        if prev_block.hash != block.prev_hash {
+            return Err(Error::BrokenLink { index });
+        };
+
+        let hash = Block::compute_hash(
+            block.timestamp,
+            &block.data,
+            &prev_block.hash,
+            block.difficulty,
+            block.nonce,
+        );
+
+        if hash != block.hash {
+            return Err(Error::HashMismatch { index });
         }
 
+        if block.timestamp <= prev_block.timestamp || block.timestamp > time_now() + MAX_CLOCK_DRIFT
+        {
+            return Err(Error::InvalidTimestamp { index });
+        }
+
+        if !Blockchain::is_valid_difficulty(prev_block, block) {
+            return Err(Error::DifficultyViolation { index });
+        }
+
+        Blockchain::validate_transactions(history, block, index)
+    }
+
+    fn is_legacy(&self) -> bool {
+        match self.chain.get(1) {
+            Some(block) => block.legacy_hash(LEGACY_GENESIS_HASH.to_string()) == block.hash,
+            None => false,
+        }
+    }
+
+    fn migrate_legacy(chain: &Blockchain) -> Result<Blockchain, Error> {
+        if chain.get_nth_block(0) != Some(&Block::get_first_block()) {
+            return Err(Error::InvalidGenesis);
+        }
+
+        let mut migrated = Blockchain::new(vec![Block::get_first_block()]);
+
         for i in 1..chain.chain.len() {
-            if chain.chain[i - 1].hash != chain.chain[i].prev_hash {
-                return false;
+            let (prev_block, block) = (&chain.chain[i - 1], &chain.chain[i]);
+            let legacy_prev_hash = if i == 1 {
+                LEGACY_GENESIS_HASH.to_string()
+            } else {
+                prev_block.hash.to_legacy()
             };
 
-            let hash = gen_hash(vec![
-                chain.chain[i].timestamp.to_string(),
-                chain.chain[i].data.to_string(),
-                chain.chain[i - 1].hash.to_string(),
-                chain.chain[i].difficulty.to_string(),
-                chain.chain[i].nonce.to_string(),
-            ]);
+            if prev_block.hash != block.prev_hash {
+                return Err(Error::BrokenLink { index: i });
+            }
+
+            if block.legacy_hash(legacy_prev_hash) != block.hash {
+                return Err(Error::HashMismatch { index: i });
+            }
 
-            if hash != chain.chain[i].hash {
-                return false;
+            // Sealing redoes the proof of work, so the legacy block has to
+            // prove it was mined at the difficulty it claims.
+            if block.timestamp <= prev_block.timestamp {
+                return Err(Error::InvalidTimestamp { index: i });
             }
+
+            if !Blockchain::is_valid_difficulty(prev_block, block) {
+                return Err(Error::DifficultyViolation { index: i });
+            }
+
+            let sealed = Block::seal(
+                block.timestamp,
+                block.data.clone(),
+                migrated.chain.last().unwrap(),
+                block.difficulty,
+            );
+            migrated.chain.push(sealed);
         }
 
-        true
+        Blockchain::validate_chain(&migrated)?;
+
+        Ok(migrated)
     }
 
-    pub fn replace_chain(&mut self, chain: &Blockchain) {
-        if chain.chain.len() > self.chain.len() && Blockchain::is_valid_chain(chain) {
-            self.chain = chain.chain.clone();
+    fn is_valid_difficulty(prev_block: &Block, block: &Block) -> bool {
+        let difficulty = block.difficulty;
+
+        if difficulty
+            != Blockchain::optimize_difficulty(prev_block, block.timestamp - prev_block.timestamp)
+        {
+            return false;
+        }
+
+        block.hash.meets_difficulty(difficulty)
+    }
+
+    fn validate_transactions(history: &[Block], block: &Block, index: usize) -> Result<(), Error> {
+        if let BlockData::Legacy(_) = block.data {
+            return Ok(());
+        }
+
+        let transactions = block
+            .data
+            .transactions()
+            .map_err(|_| Error::MalformedTransactions { index })?;
+        let (rewards, transactions): (Vec<Transaction>, Vec<Transaction>) =
+            transactions.into_iter().partition(Transaction::is_reward);
+
+        if rewards.len() != 1 {
+            return Err(Error::MissingReward { index });
+        }
+
+        Transaction::validate_reward(&rewards[0]).map_err(|reason| Error::InvalidTransaction {
+            index,
+            reason: Box::new(reason),
+        })?;
+
+        // Ids that were already mined are taken, so a signed transaction
+        // cannot be replayed in a later block.
+        let mut ids: HashSet<String> = history
+            .iter()
+            .flat_map(|block| block.data.transactions().unwrap_or_default())
+            .map(|transaction| transaction.id)
+            .collect();
+        let mut senders = HashSet::new();
+        if !ids.insert(rewards[0].id.clone()) {
+            return Err(Error::DuplicateTransaction { index });
+        }
+
+        for transaction in transactions {
+            if !ids.insert(transaction.id.clone()) || !senders.insert(transaction.sender()) {
+                return Err(Error::DuplicateTransaction { index });
+            }
+
+            if transaction.input.1 != calculate_balance_in(history, &transaction.sender()) {
+                return Err(Error::BalanceMismatch { index });
+            }
+
+            Transaction::validate_transaction(&transaction).map_err(|reason| {
+                Error::InvalidTransaction {
+                    index,
+                    reason: Box::new(reason),
+                }
+            })?;
+        }
+
+        Ok(())
+    }
+
+    pub fn replace_chain(&mut self, chain: &Blockchain) -> ReplaceChainResult {
+        let (work, new_work) = (self.total_work(), chain.total_work());
+
+        if new_work < work {
+            return ReplaceChainResult::LessWork;
+        }
+
+        if new_work == work && !Blockchain::wins_tie(chain, self) {
+            return ReplaceChainResult::TieLost;
+        }
+
+        let common = self.common_prefix(chain);
+        if let Err(e) = Blockchain::validate_from(chain, common) {
+            return ReplaceChainResult::Invalid(e);
+        }
+
+        // The chain in memory follows what reached the store, so a failed
+        // write leaves a shorter but valid chain behind.
+        if let Some(store) = self.store.as_mut() {
+            if let Err(e) = store.truncate(common) {
+                return ReplaceChainResult::StoreFailed(e);
+            }
+        }
+
+        self.chain.truncate(common);
+        for block in &chain.chain[common..] {
+            if let Err(e) = self.push(block.clone()) {
+                return ReplaceChainResult::StoreFailed(e);
+            }
+        }
+
+        ReplaceChainResult::Replaced
+    }
+
+    pub fn common_prefix(&self, chain: &Blockchain) -> usize {
+        self.chain
+            .iter()
+            .zip(chain.chain.iter())
+            .take_while(|(block, other)| block == other)
+            .count()
+    }
+
+    pub fn total_work(&self) -> u128 {
+        self.chain
+            .iter()
+            .map(|block| {
+                1u128
+                    .checked_shl(block.difficulty as u32)
+                    .unwrap_or(u128::MAX)
+            })
+            .fold(0, u128::saturating_add)
+    }
+
+    fn wins_tie(chain: &Blockchain, other: &Blockchain) -> bool {
+        match (chain.chain.last(), other.chain.last()) {
+            (Some(tip), Some(other_tip)) => tip.hash < other_tip.hash,
+            (Some(_), None) => true,
+            _ => false,
         }
     }
 
     pub fn optimize_difficulty(block: &Block, timestamp: i64) -> usize {
-        // return 2;
         let d = block.difficulty;
-        let t = block.timestamp;
 
-        if d < 1 {
-            return 1;
+        if d <= MIN_DIFFICULTY && timestamp as usize > MINING_SPEED {
+            return MIN_DIFFICULTY;
         }
 
         if timestamp as usize > MINING_SPEED {
@@ -75,9 +379,30 @@ impl Blockchain {
     }
 }
 
+// The genesis block followed by one block rewarding each wallet.
+#[cfg(test)]
+pub fn funded_chain(wallets: &[&Wallet]) -> Blockchain {
+    let mut blockchain = Blockchain::new(vec![Block::get_first_block()]);
+    for wallet in wallets {
+        blockchain
+            .add_block_with_reward(vec![], wallet.address())
+            .unwrap();
+    }
+
+    blockchain
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{
+        hashing::gen_legacy_hash,
+        storage::{BlockStore, FileStore},
+        transaction::mining_reward,
+    };
+    use proptest::prelude::*;
+    use rand::Rng;
+    use serde_json::json;
 
     fn gen_data_vec(n: usize) -> impl Strategy<Value = Vec<String>> {
         proptest::collection::vec(".*", 3..n)
@@ -92,25 +417,25 @@ mod test {
 
             for (i,v) in data.iter().enumerate() {
                 chain.push(Block::new_with_previous(v.clone(), chain.get(i).unwrap()));
-                blockchain.add_block(v.to_string());
+                blockchain.add_block(v.to_string()).unwrap();
             }
 
             for i in 1..chain.len() {
-                let hash = gen_hash(vec![
-                    blockchain.chain[i].timestamp.to_string(),
-                    blockchain.chain[i].data.to_string(),
-                    blockchain.chain[i-1].hash.to_string(),
-                    blockchain.chain[i].difficulty.to_string(),
-                    blockchain.chain[i].nonce.to_string(),
-                ]);
+                let hash = Block::compute_hash(
+                    blockchain.chain[i].timestamp,
+                    &blockchain.chain[i].data,
+                    &blockchain.chain[i-1].hash,
+                    blockchain.chain[i].difficulty,
+                    blockchain.chain[i].nonce,
+                );
                 chain[i].hash = hash;
             }
 
-            assert!(Blockchain::is_valid_chain(&blockchain));
+            assert_eq!(Blockchain::validate_chain(&blockchain), Ok(()));
             assert_eq!(blockchain.chain.len(), data.len() + 1);
             assert_eq!(*blockchain.get_nth_block(0).unwrap() , Block::get_first_block());
             for i in 0..chain.len() {
-                assert_eq!(chain[i].hash, *blockchain.get_nth_block(i).unwrap().hash);
+                assert_eq!(chain[i].hash, blockchain.get_nth_block(i).unwrap().hash);
             }
         }
 
@@ -119,13 +444,13 @@ mod test {
             let mut blockchain = Blockchain::new(vec![Block::get_first_block()]);
 
             for (i,v) in data.iter().enumerate() {
-                blockchain.add_block(v.to_string());
+                blockchain.add_block(v.to_string()).unwrap();
             }
 
             let chain_len = blockchain.chain.len();
-            blockchain.chain.get_mut(rand::thread_rng().gen_range(2, chain_len)).unwrap().data = "bar".to_string();
+            blockchain.chain.get_mut(rand::thread_rng().gen_range(2, chain_len)).unwrap().data = "bar".into();
 
-            assert_eq!(Blockchain::is_valid_chain(&blockchain), false);
+            assert!(Blockchain::validate_chain(&blockchain).is_err());
             assert_eq!(blockchain.chain.len(), data.len() + 1);
         }
 
@@ -135,11 +460,11 @@ mod test {
             let mut blockchain2 = Blockchain::new(vec![Block::get_first_block()]);
 
             for (i,v) in data.iter().enumerate() {
-                blockchain.add_block(v.to_string());
+                blockchain.add_block(v.to_string()).unwrap();
             }
 
             for (i,v) in data2.iter().enumerate() {
-                blockchain2.add_block(v.to_string());
+                blockchain2.add_block(v.to_string()).unwrap();
             }
 
             blockchain.replace_chain(&blockchain2);
@@ -147,4 +472,630 @@ mod test {
             assert_eq!(blockchain.chain.len(), std::cmp::max(data.len(), data2.len()) + 1);
         }
     }
+
+    fn forge_block(data: &str, prev_block: &Block, difficulty: usize, meets_target: bool) -> Block {
+        let delay = if difficulty < prev_block.difficulty {
+            MINING_SPEED as i64 + 1
+        } else {
+            1
+        };
+
+        forge_block_at(
+            data,
+            prev_block,
+            prev_block.timestamp + delay,
+            difficulty,
+            meets_target,
+        )
+    }
+
+    fn forge_block_at(
+        data: &str,
+        prev_block: &Block,
+        timestamp: i64,
+        difficulty: usize,
+        meets_target: bool,
+    ) -> Block {
+        for nonce in 0.. {
+            let hash =
+                Block::compute_hash(timestamp, &data.into(), &prev_block.hash, difficulty, nonce);
+
+            if hash.meets_difficulty(difficulty) == meets_target {
+                return Block {
+                    timestamp,
+                    data: data.into(),
+                    hash,
+                    prev_hash: prev_block.hash,
+                    difficulty,
+                    nonce,
+                };
+            }
+        }
+
+        unreachable!()
+    }
+
+    #[test]
+    fn test_find_block_and_page() {
+        let mut blockchain = Blockchain::new(vec![Block::get_first_block()]);
+        blockchain.add_block("foo").unwrap();
+        blockchain.add_block("bar").unwrap();
+        let block = &blockchain.chain[1];
+
+        assert_eq!(blockchain.find_block("1"), Some(block));
+        assert_eq!(blockchain.find_block(&block.hash.to_string()), Some(block));
+        assert_eq!(blockchain.find_block("3"), None);
+        assert_eq!(blockchain.find_block("foo"), None);
+        assert_eq!(blockchain.find_block(&"0".repeat(64)), None);
+
+        assert!(blockchain.find_transaction("foo").is_none());
+
+        assert_eq!(blockchain.page(1, 1), &blockchain.chain[1..2]);
+        assert_eq!(blockchain.page(1, usize::MAX), &blockchain.chain[1..]);
+        assert!(blockchain.page(5, 10).is_empty());
+    }
+
+    #[test]
+    fn test_forged_difficulty_step() {
+        let genesis = Block::get_first_block();
+        let valid = forge_block("foo", &genesis, genesis.difficulty + 1, true);
+        let jumped = forge_block("foo", &genesis, genesis.difficulty + 5, true);
+
+        assert_eq!(
+            Blockchain::validate_chain(&Blockchain::new(vec![genesis.clone(), valid])),
+            Ok(())
+        );
+        assert_eq!(
+            Blockchain::validate_chain(&Blockchain::new(vec![genesis, jumped])),
+            Err(Error::DifficultyViolation { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_forged_difficulty_drop() {
+        let genesis = Block::get_first_block();
+        let fast = forge_block_at("foo", &genesis, genesis.timestamp + 1, 2, true);
+        let slow = forge_block_at("foo", &genesis, genesis.timestamp + 1, 4, true);
+
+        assert_eq!(
+            Blockchain::validate_chain(&Blockchain::new(vec![genesis.clone(), fast])),
+            Err(Error::DifficultyViolation { index: 1 })
+        );
+        assert_eq!(
+            Blockchain::validate_chain(&Blockchain::new(vec![genesis, slow])),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_forged_timestamp() {
+        let genesis = Block::get_first_block();
+        let b1 = forge_block("foo", &genesis, 4, true);
+        let b2 = forge_block_at("foo", &b1, b1.timestamp, 5, true);
+
+        assert_eq!(
+            Blockchain::validate_chain(&Blockchain::new(vec![genesis.clone(), b1.clone(), b2])),
+            Err(Error::InvalidTimestamp { index: 2 })
+        );
+
+        let b2 = forge_block_at("foo", &b1, time_now() + MAX_CLOCK_DRIFT + 1000, 3, true);
+        assert_eq!(
+            Blockchain::validate_chain(&Blockchain::new(vec![genesis, b1, b2])),
+            Err(Error::InvalidTimestamp { index: 2 })
+        );
+    }
+
+    #[test]
+    fn test_forged_zero_difficulty() {
+        let genesis = Block::get_first_block();
+        let b1 = forge_block("foo", &genesis, 2, true);
+        let b2 = forge_block("foo", &b1, 1, true);
+        let b3 = forge_block("foo", &b2, 0, true);
+
+        assert_eq!(
+            Blockchain::validate_chain(&Blockchain::new(vec![
+                genesis.clone(),
+                b1.clone(),
+                b2.clone()
+            ])),
+            Ok(())
+        );
+        assert_eq!(
+            Blockchain::validate_chain(&Blockchain::new(vec![genesis, b1, b2, b3])),
+            Err(Error::DifficultyViolation { index: 3 })
+        );
+    }
+
+    #[test]
+    fn test_forged_proof_of_work() {
+        let genesis = Block::get_first_block();
+        let unmined = forge_block("foo", &genesis, genesis.difficulty + 1, false);
+
+        assert_eq!(
+            Blockchain::validate_chain(&Blockchain::new(vec![genesis, unmined])),
+            Err(Error::DifficultyViolation { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_replace_chain_by_work() {
+        let genesis = Block::get_first_block();
+        let mut cheap = vec![genesis.clone(), forge_block("foo", &genesis, 2, true)];
+        for _ in 0..5 {
+            let block = forge_block("foo", cheap.last().unwrap(), 1, true);
+            cheap.push(block);
+        }
+        let b1 = forge_block("foo", &genesis, 4, true);
+        let b2 = forge_block("foo", &b1, 5, true);
+        let heavy = vec![genesis, b1, b2];
+
+        let mut blockchain = Blockchain::new(heavy.clone());
+        assert_eq!(
+            blockchain.replace_chain(&Blockchain::new(cheap.clone())),
+            ReplaceChainResult::LessWork
+        );
+        assert_eq!(blockchain.chain, heavy);
+
+        let mut blockchain = Blockchain::new(cheap);
+        assert_eq!(
+            blockchain.replace_chain(&Blockchain::new(heavy.clone())),
+            ReplaceChainResult::Replaced
+        );
+        assert_eq!(blockchain.chain, heavy);
+    }
+
+    #[test]
+    fn test_replace_chain_tie() {
+        let genesis = Block::get_first_block();
+        let a = Blockchain::new(vec![genesis.clone(), forge_block("foo", &genesis, 4, true)]);
+        let b = Blockchain::new(vec![genesis.clone(), forge_block("bar", &genesis, 4, true)]);
+        assert_eq!(a.total_work(), b.total_work());
+
+        let mut a_first = Blockchain::new(a.chain.clone());
+        let mut b_first = Blockchain::new(b.chain.clone());
+        let results = (a_first.replace_chain(&b), b_first.replace_chain(&a));
+
+        assert!(
+            results == (ReplaceChainResult::Replaced, ReplaceChainResult::TieLost)
+                || results == (ReplaceChainResult::TieLost, ReplaceChainResult::Replaced)
+        );
+        assert_eq!(a_first.chain, b_first.chain);
+        assert_eq!(
+            Blockchain::new(a.chain.clone()).replace_chain(&a),
+            ReplaceChainResult::TieLost
+        );
+    }
+
+    #[test]
+    fn test_replace_chain_invalid() {
+        let genesis = Block::get_first_block();
+        let mut b1 = forge_block("foo", &genesis, 4, true);
+        b1.data = "bar".into();
+        let mut blockchain = Blockchain::new(vec![genesis.clone()]);
+
+        assert_eq!(
+            blockchain.replace_chain(&Blockchain::new(vec![genesis, b1])),
+            ReplaceChainResult::Invalid(Error::HashMismatch { index: 1 })
+        );
+        assert_eq!(blockchain.chain.len(), 1);
+    }
+
+    #[test]
+    fn test_replace_chain_validates_new_blocks_only() {
+        let genesis = Block::get_first_block();
+        let mut trusted = forge_block("foo", &genesis, 4, true);
+        trusted.nonce += 1;
+        let mut blockchain = Blockchain::new(vec![genesis, trusted]);
+
+        let mut longer = Blockchain::new(blockchain.chain.clone());
+        longer.add_block("bar").unwrap();
+        longer.add_block("baz").unwrap();
+
+        let mut tampered = Blockchain::new(longer.chain.clone());
+        tampered.chain[3].data = "qux".into();
+        assert_eq!(
+            blockchain.replace_chain(&tampered),
+            ReplaceChainResult::Invalid(Error::HashMismatch { index: 3 })
+        );
+
+        assert!(Blockchain::validate_chain(&longer).is_err());
+        assert_eq!(
+            blockchain.replace_chain(&longer),
+            ReplaceChainResult::Replaced
+        );
+    }
+
+    #[test]
+    fn test_invalid_genesis() {
+        let mut genesis = Block::get_first_block();
+        genesis.data = "foo".into();
+
+        assert_eq!(
+            Blockchain::validate_chain(&Blockchain::new(vec![genesis])),
+            Err(Error::InvalidGenesis)
+        );
+        assert_eq!(
+            Blockchain::validate_chain(&Blockchain::new(vec![])),
+            Err(Error::InvalidGenesis)
+        );
+    }
+
+    #[test]
+    fn test_broken_link() {
+        let mut blockchain = Blockchain::new(vec![Block::get_first_block()]);
+        blockchain.add_block("foo").unwrap();
+        blockchain.add_block("bar").unwrap();
+        blockchain.chain[2].prev_hash = BlockHash::digest(b"foo");
+
+        assert_eq!(
+            Blockchain::validate_chain(&blockchain),
+            Err(Error::BrokenLink { index: 2 })
+        );
+    }
+
+    fn legacy_chain_json(data: &[&str]) -> String {
+        let genesis = Block::get_first_block();
+        let mut blocks = vec![json!({
+            "timestamp": 0,
+            "data": "empty_data",
+            "hash": "empty_hash",
+            "prev_hash": "empty_previous_hash",
+            "difficulty": genesis.difficulty,
+            "nonce": 0,
+        })];
+        let mut prev_hash = LEGACY_GENESIS_HASH.to_string();
+        let mut difficulty = genesis.difficulty;
+
+        for (i, v) in data.iter().enumerate() {
+            let timestamp = i + 1;
+            difficulty += 1;
+
+            for nonce in 0.. {
+                let hash = gen_legacy_hash(vec![
+                    timestamp.to_string(),
+                    v.to_string(),
+                    prev_hash.clone(),
+                    difficulty.to_string(),
+                    nonce.to_string(),
+                ]);
+
+                if hash.meets_difficulty(difficulty) {
+                    blocks.push(json!({
+                        "timestamp": timestamp,
+                        "data": v,
+                        "hash": hash.to_legacy(),
+                        "prev_hash": prev_hash,
+                        "difficulty": difficulty,
+                        "nonce": nonce,
+                    }));
+                    prev_hash = hash.to_legacy();
+                    break;
+                }
+            }
+        }
+
+        json!(blocks).to_string()
+    }
+
+    #[test]
+    fn test_migrate_legacy_chain() {
+        let legacy: Vec<Block> = serde_json::from_str(&legacy_chain_json(&["foo", "bar"])).unwrap();
+        let legacy = Blockchain::new(legacy);
+
+        assert_eq!(legacy.chain[0], Block::get_first_block());
+        assert!(Blockchain::validate_chain(&legacy).is_err());
+        assert!(legacy.is_legacy());
+        assert!(matches!(
+            Blockchain::new(vec![Block::get_first_block()]).replace_chain(&legacy),
+            ReplaceChainResult::Invalid(_)
+        ));
+
+        let migrated = Blockchain::migrate_legacy(&legacy).unwrap();
+
+        assert!(!migrated.is_legacy());
+        assert_eq!(Blockchain::validate_chain(&migrated), Ok(()));
+        assert_eq!(migrated.chain.len(), 3);
+        for (legacy_block, block) in legacy.chain.iter().zip(migrated.chain.iter()).skip(1) {
+            assert_eq!(legacy_block.timestamp, block.timestamp);
+            assert_eq!(legacy_block.data, block.data);
+            assert_eq!(legacy_block.difficulty, block.difficulty);
+        }
+    }
+
+    #[test]
+    fn test_open_migrates_legacy_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy: Vec<Block> = serde_json::from_str(&legacy_chain_json(&["foo", "bar"])).unwrap();
+        let mut store = FileStore::open(dir.path()).unwrap();
+        store.load().unwrap();
+        for block in &legacy {
+            store.append(block).unwrap();
+        }
+
+        let open = || Blockchain::open(Box::new(FileStore::open(dir.path()).unwrap())).unwrap();
+        let migrated = open();
+
+        assert!(!migrated.is_legacy());
+        assert_eq!(migrated.chain.len(), 3);
+        assert_eq!(migrated.chain[2].data, legacy[2].data);
+        assert_eq!(open().chain, migrated.chain);
+    }
+
+    #[test]
+    fn test_migrate_tampered_legacy_chain() {
+        let mut legacy: Vec<Block> =
+            serde_json::from_str(&legacy_chain_json(&["foo", "bar"])).unwrap();
+        legacy[2].data = "baz".into();
+
+        assert_eq!(
+            Blockchain::migrate_legacy(&Blockchain::new(legacy)).err(),
+            Some(Error::HashMismatch { index: 2 })
+        );
+    }
+
+    #[test]
+    fn test_migrate_unmined_legacy_chain() {
+        let mut legacy: serde_json::Value =
+            serde_json::from_str(&legacy_chain_json(&["foo"])).unwrap();
+        let difficulty = 60;
+        legacy[1]["difficulty"] = json!(difficulty);
+        legacy[1]["nonce"] = json!(0);
+        legacy[1]["hash"] = json!(gen_legacy_hash(vec![
+            "1".to_string(),
+            "foo".to_string(),
+            LEGACY_GENESIS_HASH.to_string(),
+            difficulty.to_string(),
+            "0".to_string(),
+        ])
+        .to_legacy());
+        let legacy: Vec<Block> = serde_json::from_value(legacy).unwrap();
+
+        assert_eq!(
+            Blockchain::migrate_legacy(&Blockchain::new(legacy)).err(),
+            Some(Error::DifficultyViolation { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_difficulty_floor() {
+        let mut block = Block::get_first_block();
+        block.difficulty = MIN_DIFFICULTY;
+
+        assert_eq!(
+            Blockchain::optimize_difficulty(&block, MINING_SPEED as i64 + 1),
+            MIN_DIFFICULTY
+        );
+        assert_eq!(
+            Blockchain::optimize_difficulty(&block, 0),
+            MIN_DIFFICULTY + 1
+        );
+    }
+
+    #[test]
+    fn test_chain_with_transactions() {
+        let mut f_wallet = Wallet::new(0);
+        let mut blockchain = funded_chain(&[&f_wallet]);
+        let tx = f_wallet
+            .create_transaction(11, Wallet::new(0).address(), &blockchain)
+            .unwrap();
+
+        blockchain
+            .add_block_with_reward(vec![tx.clone()], Wallet::new(0).address())
+            .unwrap();
+
+        assert_eq!(Blockchain::validate_chain(&blockchain), Ok(()));
+        assert_eq!(
+            blockchain
+                .find_transaction(&tx.id)
+                .map(|(height, found)| (height, found.input)),
+            Some((2, tx.input))
+        );
+    }
+
+    #[test]
+    fn test_chain_with_invalid_transaction() {
+        let mut f_wallet = Wallet::new(0);
+        let mut blockchain = funded_chain(&[&f_wallet]);
+        let mut tx = f_wallet
+            .create_transaction(11, Wallet::new(0).address(), &blockchain)
+            .unwrap();
+        *tx.output_map.get_mut(&f_wallet.address()).unwrap() = 999;
+
+        blockchain
+            .add_block_with_reward(vec![tx], Wallet::new(0).address())
+            .unwrap();
+
+        assert_eq!(
+            Blockchain::validate_chain(&blockchain),
+            Err(Error::InvalidTransaction {
+                index: 2,
+                reason: Box::new(Error::OutputMismatch)
+            })
+        );
+    }
+
+    #[test]
+    fn test_chain_with_invalid_signature() {
+        let mut f_wallet = Wallet::new(0);
+        let mut blockchain = funded_chain(&[&f_wallet]);
+        let mut tx = f_wallet
+            .create_transaction(11, Wallet::new(0).address(), &blockchain)
+            .unwrap();
+        tx.input.3 = Wallet::new(0).sign("foo".to_owned()).unwrap();
+
+        blockchain
+            .add_block_with_reward(vec![tx], Wallet::new(0).address())
+            .unwrap();
+
+        assert_eq!(
+            Blockchain::validate_chain(&blockchain),
+            Err(Error::InvalidTransaction {
+                index: 2,
+                reason: Box::new(Error::BadSignature)
+            })
+        );
+    }
+
+    #[test]
+    fn test_chain_with_inflated_input_balance() {
+        let mut blockchain = funded_chain(&[&Wallet::new(0)]);
+        let tx = Transaction::new(&Wallet::new(1000), Wallet::new(0).address(), 11).unwrap();
+
+        blockchain
+            .add_block_with_reward(vec![tx], Wallet::new(0).address())
+            .unwrap();
+
+        assert_eq!(
+            Blockchain::validate_chain(&blockchain),
+            Err(Error::BalanceMismatch { index: 2 })
+        );
+    }
+
+    #[test]
+    fn test_chain_with_duplicate_transaction() {
+        let mut f_wallet = Wallet::new(0);
+        let mut blockchain = funded_chain(&[&f_wallet]);
+        let tx = f_wallet
+            .create_transaction(11, Wallet::new(0).address(), &blockchain)
+            .unwrap();
+
+        blockchain
+            .add_block_with_reward(vec![tx.clone(), tx], Wallet::new(0).address())
+            .unwrap();
+
+        assert_eq!(
+            Blockchain::validate_chain(&blockchain),
+            Err(Error::DuplicateTransaction { index: 2 })
+        );
+    }
+
+    #[test]
+    fn test_chain_with_double_spend_in_block() {
+        let mut f_wallet = Wallet::new(0);
+        let mut blockchain = funded_chain(&[&f_wallet]);
+        let tx1 = f_wallet
+            .create_transaction(40, Wallet::new(0).address(), &blockchain)
+            .unwrap();
+        let tx2 = f_wallet
+            .create_transaction(40, Wallet::new(0).address(), &blockchain)
+            .unwrap();
+
+        blockchain
+            .add_block_with_reward(vec![tx1, tx2], Wallet::new(0).address())
+            .unwrap();
+
+        assert_eq!(
+            Blockchain::validate_chain(&blockchain),
+            Err(Error::DuplicateTransaction { index: 2 })
+        );
+    }
+
+    #[test]
+    fn test_chain_with_replayed_transaction() {
+        let (mut sender, mut other) = (Wallet::new(0), Wallet::new(0));
+        let mut blockchain = funded_chain(&[&sender, &other]);
+        let tx = sender
+            .create_transaction(20, Wallet::new(0).address(), &blockchain)
+            .unwrap();
+        let refund = other
+            .create_transaction(20, sender.address(), &blockchain)
+            .unwrap();
+
+        blockchain
+            .add_block_with_reward(vec![tx.clone(), refund], Wallet::new(0).address())
+            .unwrap();
+        assert_eq!(
+            calculate_balance_in(&blockchain.chain, &sender.address()),
+            tx.input.1
+        );
+        assert_eq!(Blockchain::validate_chain(&blockchain), Ok(()));
+
+        blockchain
+            .add_block_with_reward(vec![tx], Wallet::new(0).address())
+            .unwrap();
+        assert_eq!(
+            Blockchain::validate_chain(&blockchain),
+            Err(Error::DuplicateTransaction { index: 4 })
+        );
+    }
+
+    #[test]
+    fn test_replace_chain_with_double_spend() {
+        let mut blockchain = Blockchain::new(vec![Block::get_first_block()]);
+        let mut f_wallet = Wallet::new(0);
+        let mut blockchain2 = funded_chain(&[&f_wallet]);
+        let tx = f_wallet
+            .create_transaction(40, Wallet::new(0).address(), &blockchain2)
+            .unwrap();
+
+        blockchain2
+            .add_block_with_reward(vec![tx.clone()], Wallet::new(0).address())
+            .unwrap();
+        blockchain2
+            .add_block_with_reward(vec![tx], Wallet::new(0).address())
+            .unwrap();
+        blockchain.replace_chain(&blockchain2);
+
+        assert_eq!(blockchain.chain.len(), 1);
+    }
+
+    #[test]
+    fn test_chain_without_reward() {
+        let mut blockchain = Blockchain::new(vec![Block::get_first_block()]);
+        let tx = Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap();
+
+        blockchain.add_block(vec![tx]).unwrap();
+
+        assert_eq!(
+            Blockchain::validate_chain(&blockchain),
+            Err(Error::MissingReward { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_chain_with_multiple_rewards() {
+        let mut blockchain = Blockchain::new(vec![Block::get_first_block()]);
+        let miner = Wallet::new(0).address();
+
+        blockchain
+            .add_block_with_reward(vec![Transaction::reward(miner)], miner)
+            .unwrap();
+
+        assert_eq!(
+            Blockchain::validate_chain(&blockchain),
+            Err(Error::MissingReward { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_chain_with_inflated_reward() {
+        let mut blockchain = Blockchain::new(vec![Block::get_first_block()]);
+        let miner = Wallet::new(0).address();
+        let mut reward = Transaction::reward(miner);
+        *reward.output_map.get_mut(&miner).unwrap() = mining_reward() * 2;
+
+        blockchain.add_block(vec![reward]).unwrap();
+
+        assert_eq!(
+            Blockchain::validate_chain(&blockchain),
+            Err(Error::InvalidTransaction {
+                index: 1,
+                reason: Box::new(Error::InvalidReward)
+            })
+        );
+    }
+
+    #[test]
+    fn test_chain_with_undecodable_transaction() {
+        let mut blockchain = Blockchain::new(vec![Block::get_first_block()]);
+
+        blockchain
+            .add_block(BlockData::Transactions(vec!["foo".to_string()]))
+            .unwrap();
+
+        assert_eq!(
+            Blockchain::validate_chain(&blockchain),
+            Err(Error::MalformedTransactions { index: 1 })
+        );
+    }
 }
@@ -0,0 +1,97 @@
+use secp256k1::{PublicKey, Signature};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+// Keys are written as compressed hex and signatures as compact hex in
+// human-readable formats, and as the same raw bytes in binary ones.
+
+struct Key(PublicKey);
+
+struct Sig(Signature);
+
+fn serialize_bytes<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+    if s.is_human_readable() {
+        s.serialize_str(&hex::encode(bytes))
+    } else {
+        s.serialize_bytes(bytes)
+    }
+}
+
+fn deserialize_bytes<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+    if d.is_human_readable() {
+        let value = String::deserialize(d)?;
+
+        hex::decode(&value).map_err(de::Error::custom)
+    } else {
+        d.deserialize_bytes(BytesVisitor)
+    }
+}
+
+pub struct BytesVisitor;
+
+impl<'de> de::Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a byte array")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(v)
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(&self.0.serialize(), s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let bytes = deserialize_bytes(d)?;
+
+        PublicKey::from_slice(&bytes)
+            .map(Key)
+            .map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Sig {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(&self.0.serialize_compact(), s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Sig {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let bytes = deserialize_bytes(d)?;
+
+        Signature::from_compact(&bytes)
+            .map(Sig)
+            .map_err(de::Error::custom)
+    }
+}
+
+pub mod input {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        (timestamp, balance, address, signature): &(i64, i64, PublicKey, Signature),
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        (timestamp, balance, Key(*address), Sig(*signature)).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<(i64, i64, PublicKey, Signature), D::Error> {
+        let (timestamp, balance, address, signature) = <(i64, i64, Key, Sig)>::deserialize(d)?;
+
+        Ok((timestamp, balance, address.0, signature.0))
+    }
+}
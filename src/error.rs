@@ -0,0 +1,141 @@
+use crate::address::AddressError;
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde_json::json;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    InsufficientBalance,
+    InvalidAmount,
+    BadSignature,
+    OutputMismatch,
+    StaleInput,
+    InvalidReward,
+    InvalidGenesis,
+    BrokenLink { index: usize },
+    HashMismatch { index: usize },
+    DifficultyViolation { index: usize },
+    InvalidTimestamp { index: usize },
+    MalformedTransactions { index: usize },
+    MissingReward { index: usize },
+    DuplicateTransaction { index: usize },
+    BalanceMismatch { index: usize },
+    InvalidTransaction { index: usize, reason: Box<Error> },
+    InvalidSecretKey,
+    UnknownAddress,
+    WrongPassphrase,
+    MissingPassphrase,
+    MissingWallet,
+    InvalidKeyFile,
+    InvalidMnemonic,
+    InvalidDerivationPath,
+    InvalidAddress(AddressError),
+    Secp256k1(secp256k1::Error),
+    Io(String),
+    CorruptStorage { offset: u64 },
+    Transport(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InsufficientBalance => write!(f, "not enough balance"),
+            Error::InvalidAmount => write!(f, "amount must be between 1 and {}", i64::MAX),
+            Error::BadSignature => write!(f, "transaction signature is invalid"),
+            Error::OutputMismatch => write!(f, "transaction outputs do not match its input"),
+            Error::StaleInput => write!(f, "transaction input does not match the sender's balance"),
+            Error::InvalidReward => write!(f, "reward transaction is invalid"),
+            Error::InvalidGenesis => write!(f, "chain does not start with the genesis block"),
+            Error::BrokenLink { index } => {
+                write!(f, "block {} does not link to the previous block", index)
+            }
+            Error::HashMismatch { index } => write!(f, "block {} has a wrong hash", index),
+            Error::DifficultyViolation { index } => {
+                write!(f, "block {} violates the difficulty rules", index)
+            }
+            Error::InvalidTimestamp { index } => {
+                write!(f, "block {} has a timestamp out of range", index)
+            }
+            Error::MalformedTransactions { index } => {
+                write!(f, "block {} contains undecodable transactions", index)
+            }
+            Error::MissingReward { index } => {
+                write!(f, "block {} must contain exactly one reward", index)
+            }
+            Error::DuplicateTransaction { index } => {
+                write!(f, "block {} contains a duplicate transaction", index)
+            }
+            Error::BalanceMismatch { index } => write!(
+                f,
+                "block {} contains a transaction with a wrong input balance",
+                index
+            ),
+            Error::InvalidTransaction { index, reason } => {
+                write!(
+                    f,
+                    "block {} contains an invalid transaction: {}",
+                    index, reason
+                )
+            }
+            Error::InvalidSecretKey => write!(f, "secret key is invalid"),
+            Error::UnknownAddress => write!(f, "no wallet stored for this address"),
+            Error::WrongPassphrase => write!(f, "wrong passphrase"),
+            Error::MissingPassphrase => write!(f, "MYOX_PASSPHRASE must be set"),
+            Error::MissingWallet => write!(
+                f,
+                "keystore is empty, set MYOX_MNEMONIC or MYOX_IMPORT_KEY to add a wallet"
+            ),
+            Error::InvalidKeyFile => write!(f, "key file is corrupted"),
+            Error::InvalidMnemonic => write!(f, "mnemonic phrase is invalid"),
+            Error::InvalidDerivationPath => write!(f, "derivation path is invalid"),
+            Error::InvalidAddress(e) => write!(f, "invalid address: {}", e),
+            Error::Secp256k1(e) => write!(f, "secp256k1 error: {}", e),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::CorruptStorage { offset } => {
+                write!(f, "block log is corrupted at offset {}", offset)
+            }
+            Error::Transport(e) => write!(f, "transport error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::InsufficientBalance | Error::InvalidAmount | Error::InvalidAddress(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({ "error": self.to_string() }))
+    }
+}
+
+impl From<secp256k1::Error> for Error {
+    fn from(e: secp256k1::Error) -> Self {
+        Error::Secp256k1(e)
+    }
+}
+
+impl From<AddressError> for Error {
+    fn from(e: AddressError) -> Self {
+        Error::InvalidAddress(e)
+    }
+}
+
+impl From<redis::RedisError> for Error {
+    fn from(e: redis::RedisError) -> Self {
+        Error::Transport(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e.to_string())
+    }
+}
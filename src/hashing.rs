@@ -1,24 +1,174 @@
+use ripemd160::Ripemd160;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Digest, Sha256};
+use std::fmt;
 
-pub fn gen_hash(parts: Vec<String>) -> String {
+pub const LEGACY_GENESIS_HASH: &str = "empty_hash";
+pub const LEGACY_GENESIS_PREV_HASH: &str = "empty_previous_hash";
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct BlockHash(pub [u8; 32]);
+
+impl BlockHash {
+    pub fn digest(input: &[u8]) -> Self {
+        let mut hash = [0; 32];
+        hash.copy_from_slice(&Sha256::digest(input));
+
+        BlockHash(hash)
+    }
+
+    pub fn leading_zero_bits(&self) -> usize {
+        let mut bits = 0;
+
+        for byte in self.0.iter() {
+            bits += byte.leading_zeros() as usize;
+
+            if *byte != 0 {
+                break;
+            }
+        }
+
+        bits
+    }
+
+    pub fn meets_difficulty(&self, difficulty: usize) -> bool {
+        self.leading_zero_bits() >= difficulty
+    }
+
+    pub fn from_hex(hex_hash: &str) -> Option<Self> {
+        let mut hash = [0; 32];
+        hex::decode_to_slice(hex_hash, &mut hash).ok()?;
+
+        Some(BlockHash(hash))
+    }
+
+    pub fn from_legacy(legacy_hash: &str) -> Option<Self> {
+        if legacy_hash == LEGACY_GENESIS_HASH || legacy_hash == LEGACY_GENESIS_PREV_HASH {
+            return Some(BlockHash::digest(legacy_hash.as_bytes()));
+        }
+
+        if legacy_hash.len() != 256 {
+            return None;
+        }
+
+        let mut hash = [0; 32];
+        for (i, bit) in legacy_hash.chars().enumerate() {
+            match bit {
+                '0' => {}
+                '1' => hash[i / 8] |= 0x80 >> (i % 8),
+                _ => return None,
+            }
+        }
+
+        Some(BlockHash(hash))
+    }
+
+    pub fn to_legacy(self) -> String {
+        self.0.iter().map(|byte| format!("{:08b}", byte)).collect()
+    }
+}
+
+impl fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BlockHash({})", self)
+    }
+}
+
+impl Serialize for BlockHash {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockHash {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let hash = String::deserialize(d)?;
+
+        BlockHash::from_hex(&hash)
+            .or_else(|| BlockHash::from_legacy(&hash))
+            .ok_or_else(|| de::Error::custom(format!("invalid block hash: {}", hash)))
+    }
+}
+
+pub fn gen_legacy_hash(parts: Vec<String>) -> BlockHash {
     let input: String = parts.into_iter().collect();
 
-    format!("{:x}", Sha256::digest(&input.as_bytes()))
-        .chars()
-        .map(|v| i64::from_str_radix(&format!("{}", v), 16).unwrap())
-        .map(|v| {
-            let bin = format!("{:b}", v);
-            format!("{}{}", "0".repeat(4 - bin.to_string().len()), bin)
-        })
-        .collect::<String>()
-}
-
-pub fn to_binary(string: &str) -> String {
-    Sha256::digest(&string.as_bytes())
-        .iter()
-        .map(|v| format!("{:b}", v))
-        .collect::<String>()
-        .chars()
-        .rev()
-        .collect()
+    BlockHash::digest(input.as_bytes())
+}
+
+pub fn hash160(input: &[u8]) -> [u8; 20] {
+    let mut hash = [0; 20];
+    hash.copy_from_slice(&Ripemd160::digest(&Sha256::digest(input)));
+
+    hash
+}
+
+pub fn checksum(input: &[u8]) -> [u8; 4] {
+    let mut checksum = [0; 4];
+    checksum.copy_from_slice(&Sha256::digest(&Sha256::digest(input))[..4]);
+
+    checksum
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_leading_zero_bits() {
+        let mut hash = BlockHash([0xff; 32]);
+        assert_eq!(hash.leading_zero_bits(), 0);
+
+        hash.0[0] = 0;
+        hash.0[1] = 0b0001_0000;
+        assert_eq!(hash.leading_zero_bits(), 11);
+        assert!(hash.meets_difficulty(11));
+        assert!(!hash.meets_difficulty(12));
+
+        assert_eq!(BlockHash([0; 32]).leading_zero_bits(), 256);
+    }
+
+    #[test]
+    fn test_hex_serde() {
+        let hash = BlockHash::digest(b"foo");
+        let json = serde_json::to_string(&hash).unwrap();
+
+        assert_eq!(json.len(), 64 + 2);
+        assert_eq!(serde_json::from_str::<BlockHash>(&json).unwrap(), hash);
+    }
+
+    #[test]
+    fn test_legacy_bit_string() {
+        let hash = gen_legacy_hash(vec!["fo".to_string(), "o".to_string()]);
+        let legacy = format!("{:x}", Sha256::digest(b"foo"))
+            .chars()
+            .map(|v| format!("{:04b}", v.to_digit(16).unwrap()))
+            .collect::<String>();
+
+        assert_eq!(hash.to_legacy(), legacy);
+        assert_eq!(BlockHash::from_legacy(&legacy), Some(hash));
+        assert_eq!(
+            serde_json::from_str::<BlockHash>(&format!("\"{}\"", legacy)).unwrap(),
+            hash
+        );
+        assert!(serde_json::from_str::<BlockHash>("\"foo\"").is_err());
+    }
+
+    #[test]
+    fn test_hash160() {
+        let generator =
+            hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                .unwrap();
+
+        assert_eq!(
+            hex::encode(hash160(&generator)),
+            "751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+    }
 }
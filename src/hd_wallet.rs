@@ -0,0 +1,345 @@
+use crate::{error::Error, wallet::Wallet};
+use bip39::{Language, Mnemonic, MnemonicType, Seed};
+use hmac::{Hmac, Mac};
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use sha2::Sha512;
+
+pub const HARDENED: u32 = 1 << 31;
+pub const DEFAULT_PATH: &str = "m/0'/0'/0'";
+
+#[derive(Clone)]
+pub struct ExtendedKey {
+    secret_key: SecretKey,
+    chain_code: [u8; 32],
+    depth: u8,
+}
+
+impl ExtendedKey {
+    pub fn from_seed(seed: &[u8]) -> Result<Self, Error> {
+        let (secret_key, chain_code) = hmac_sha512(b"Bitcoin seed", seed);
+
+        Ok(ExtendedKey {
+            secret_key: SecretKey::from_slice(&secret_key)?,
+            chain_code,
+            depth: 0,
+        })
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), &self.secret_key)
+    }
+
+    pub fn derive_child(&self, index: u32) -> Result<Self, Error> {
+        let mut data = Vec::with_capacity(37);
+        if index >= HARDENED {
+            data.push(0);
+            data.extend_from_slice(&self.secret_key[..]);
+        } else {
+            data.extend_from_slice(&self.public_key().serialize());
+        }
+        data.extend_from_slice(&index.to_be_bytes());
+
+        let (tweak, chain_code) = hmac_sha512(&self.chain_code, &data);
+        let mut secret_key = self.secret_key;
+        secret_key.add_assign(&tweak)?;
+
+        Ok(ExtendedKey {
+            secret_key,
+            chain_code,
+            depth: self
+                .depth
+                .checked_add(1)
+                .ok_or(Error::InvalidDerivationPath)?,
+        })
+    }
+
+    pub fn derive_path(&self, path: &str) -> Result<Self, Error> {
+        let mut parts = path.split('/');
+
+        if parts.next() != Some("m") {
+            return Err(Error::InvalidDerivationPath);
+        }
+
+        parts.try_fold(self.clone(), |key, part| {
+            let (index, hardened) = match part.strip_suffix('\'').or_else(|| part.strip_suffix('h'))
+            {
+                Some(index) => (index, true),
+                None => (part, false),
+            };
+            let index: u32 = index.parse().map_err(|_| Error::InvalidDerivationPath)?;
+
+            if index >= HARDENED {
+                return Err(Error::InvalidDerivationPath);
+            }
+
+            key.derive_child(if hardened { index | HARDENED } else { index })
+        })
+    }
+
+    pub fn wallet(&self) -> Wallet {
+        Wallet::from_secret_key(self.secret_key, 0)
+    }
+}
+
+fn hmac_sha512(key: &[u8], data: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let mut mac = Hmac::<Sha512>::new_varkey(key).expect("HMAC accepts all key sizes");
+    mac.input(data);
+    let result = mac.result().code();
+
+    let (mut left, mut right) = ([0; 32], [0; 32]);
+    left.copy_from_slice(&result[..32]);
+    right.copy_from_slice(&result[32..]);
+
+    (left, right)
+}
+
+pub struct HdWallet {
+    mnemonic: Mnemonic,
+    master: ExtendedKey,
+}
+
+impl HdWallet {
+    pub fn generate(passphrase: &str) -> Self {
+        let mnemonic = Mnemonic::new(MnemonicType::Words24, Language::English);
+
+        HdWallet::with_mnemonic(mnemonic, passphrase).expect("generated seed is valid")
+    }
+
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<Self, Error> {
+        let mnemonic =
+            Mnemonic::from_phrase(phrase, Language::English).map_err(|_| Error::InvalidMnemonic)?;
+
+        HdWallet::with_mnemonic(mnemonic, passphrase)
+    }
+
+    fn with_mnemonic(mnemonic: Mnemonic, passphrase: &str) -> Result<Self, Error> {
+        let master = ExtendedKey::from_seed(Seed::new(&mnemonic, passphrase).as_bytes())?;
+
+        Ok(HdWallet { mnemonic, master })
+    }
+
+    pub fn mnemonic(&self) -> &str {
+        self.mnemonic.phrase()
+    }
+
+    pub fn wallet(&self, path: &str) -> Result<Wallet, Error> {
+        Ok(self.master.derive_path(path)?.wallet())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hashing::{checksum, hash160};
+
+    const XPRV_VERSION: [u8; 4] = [0x04, 0x88, 0xad, 0xe4];
+    const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+
+    // Extended keys are only serialized to check the test vectors, so the
+    // parent fingerprint and child number are taken from the path.
+    fn serialize(master: &ExtendedKey, path: &str) -> (String, String) {
+        let key = master.derive_path(path).unwrap();
+        let (parent_fingerprint, child_number) = match path.rfind('/') {
+            Some(i) => {
+                let parent = master.derive_path(&path[..i]).unwrap();
+                let child = &path[i + 1..];
+                let child_number = match child.strip_suffix('\'') {
+                    Some(index) => index.parse::<u32>().unwrap() | HARDENED,
+                    None => child.parse().unwrap(),
+                };
+
+                (fingerprint(&parent), child_number)
+            }
+            None => ([0; 4], 0),
+        };
+        let encode = |version: [u8; 4], data: &[u8]| {
+            let mut encoded = Vec::with_capacity(82);
+            encoded.extend_from_slice(&version);
+            encoded.push(key.depth);
+            encoded.extend_from_slice(&parent_fingerprint);
+            encoded.extend_from_slice(&child_number.to_be_bytes());
+            encoded.extend_from_slice(&key.chain_code);
+            encoded.extend_from_slice(data);
+            encoded.extend_from_slice(&checksum(&encoded));
+
+            bs58::encode(encoded).into_string()
+        };
+
+        let mut secret_key = vec![0];
+        secret_key.extend_from_slice(&key.secret_key[..]);
+
+        (
+            encode(XPRV_VERSION, &secret_key),
+            encode(XPUB_VERSION, &key.public_key().serialize()),
+        )
+    }
+
+    fn fingerprint(key: &ExtendedKey) -> [u8; 4] {
+        let mut fingerprint = [0; 4];
+        fingerprint.copy_from_slice(&hash160(&key.public_key().serialize())[..4]);
+
+        fingerprint
+    }
+
+    // https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki#test-vectors
+    const BIP32_VECTOR_1: &[(&str, &str, &str)] = &[
+        (
+            "m",
+            "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi",
+            "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8",
+        ),
+        (
+            "m/0'",
+            "xprv9uHRZZhk6KAJC1avXpDAp4MDc3sQKNxDiPvvkX8Br5ngLNv1TxvUxt4cV1rGL5hj6KCesnDYUhd7oWgT11eZG7XnxHrnYeSvkzY7d2bhkJ7",
+            "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw",
+        ),
+        (
+            "m/0'/1",
+            "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs",
+            "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ",
+        ),
+        (
+            "m/0'/1/2'",
+            "xprv9z4pot5VBttmtdRTWfWQmoH1taj2axGVzFqSb8C9xaxKymcFzXBDptWmT7FwuEzG3ryjH4ktypQSAewRiNMjANTtpgP4mLTj34bhnZX7UiM",
+            "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5",
+        ),
+        (
+            "m/0'/1/2'/2",
+            "xprvA2JDeKCSNNZky6uBCviVfJSKyQ1mDYahRjijr5idH2WwLsEd4Hsb2Tyh8RfQMuPh7f7RtyzTtdrbdqqsunu5Mm3wDvUAKRHSC34sJ7in334",
+            "xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjTAwm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV",
+        ),
+        (
+            "m/0'/1/2'/2/1000000000",
+            "xprvA41z7zogVVwxVSgdKUHDy1SKmdb533PjDz7J6N6mV6uS3ze1ai8FHa8kmHScGpWmj4WggLyQjgPie1rFSruoUihUZREPSL39UNdE3BBDu76",
+            "xpub6H1LXWLaKsWFhvm6RVpEL9P4KfRZSW7abD2ttkWP3SSQvnyA8FSVqNTEcYFgJS2UaFcxupHiYkro49S8yGasTvXEYBVPamhGW6cFJodrTHy",
+        ),
+    ];
+
+    const BIP32_VECTOR_2: &[(&str, &str, &str)] = &[
+        (
+            "m",
+            "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U",
+            "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB",
+        ),
+        (
+            "m/0",
+            "xprv9vHkqa6EV4sPZHYqZznhT2NPtPCjKuDKGY38FBWLvgaDx45zo9WQRUT3dKYnjwih2yJD9mkrocEZXo1ex8G81dwSM1fwqWpWkeS3v86pgKt",
+            "xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH",
+        ),
+        (
+            "m/0/2147483647'",
+            "xprv9wSp6B7kry3Vj9m1zSnLvN3xH8RdsPP1Mh7fAaR7aRLcQMKTR2vidYEeEg2mUCTAwCd6vnxVrcjfy2kRgVsFawNzmjuHc2YmYRmagcEPdU9",
+            "xpub6ASAVgeehLbnwdqV6UKMHVzgqAG8Gr6riv3Fxxpj8ksbH9ebxaEyBLZ85ySDhKiLDBrQSARLq1uNRts8RuJiHjaDMBU4Zn9h8LZNnBC5y4a",
+        ),
+    ];
+
+    // https://github.com/trezor/python-mnemonic/blob/master/vectors.json
+    const BIP39_VECTORS: &[(&str, &str, &str)] = &[
+        (
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+            "xprv9s21ZrQH143K3h3fDYiay8mocZ3afhfULfb5GX8kCBdno77K4HiA15Tg23wpbeF1pLfs1c5SPmYHrEpTuuRhxMwvKDwqdKiGJS9XFKzUsAF",
+        ),
+        (
+            "legal winner thank year wave sausage worth useful legal winner thank yellow",
+            "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607",
+            "xprv9s21ZrQH143K2gA81bYFHqU68xz1cX2APaSq5tt6MFSLeXnCKV1RVUJt9FWNTbrrryem4ZckN8k4Ls1H6nwdvDTvnV7zEXs2HgPezuVccsq",
+        ),
+        (
+            "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+            "d71de856f81a8acc65e6fc851a38d4d7ec216fd0796d0a6827a3ad6ed5511a30fa280f12eb2e47ed2ac03b5c462a0358d18d69fe4f985ec81778c1b370b652a8",
+            "xprv9s21ZrQH143K2shfP28KM3nr5Ap1SXjz8gc2rAqqMEynmjt6o1qboCDpxckqXavCwdnYds6yBHZGKHv7ef2eTXy461PXUjBFQg6PrwY4Gzq",
+        ),
+    ];
+
+    fn check_bip32_vector(seed: &str, vector: &[(&str, &str, &str)]) {
+        let master = ExtendedKey::from_seed(&hex::decode(seed).unwrap()).unwrap();
+
+        for (path, xprv, xpub) in vector {
+            assert_eq!(
+                serialize(&master, path),
+                (xprv.to_string(), xpub.to_string()),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_bip32_vector_1() {
+        check_bip32_vector("000102030405060708090a0b0c0d0e0f", BIP32_VECTOR_1);
+    }
+
+    #[test]
+    fn test_bip32_vector_2() {
+        check_bip32_vector(
+            concat!(
+                "fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a2",
+                "9f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542"
+            ),
+            BIP32_VECTOR_2,
+        );
+    }
+
+    #[test]
+    fn test_bip39_vectors() {
+        for (phrase, seed, xprv) in BIP39_VECTORS {
+            let mnemonic = Mnemonic::from_phrase(phrase, Language::English).unwrap();
+            assert_eq!(
+                hex::encode(Seed::new(&mnemonic, "TREZOR").as_bytes()),
+                *seed
+            );
+
+            let wallet = HdWallet::from_mnemonic(phrase, "TREZOR").unwrap();
+            assert_eq!(wallet.mnemonic(), *phrase);
+            assert_eq!(serialize(&wallet.master, "m").0, *xprv);
+        }
+    }
+
+    #[test]
+    fn test_derived_wallet_is_reproducible() {
+        let generated = HdWallet::generate("");
+        let recovered = HdWallet::from_mnemonic(generated.mnemonic(), "").unwrap();
+
+        assert_eq!(generated.mnemonic().split(' ').count(), 24);
+        assert_eq!(
+            generated.wallet(DEFAULT_PATH).unwrap().public_key,
+            recovered.wallet(DEFAULT_PATH).unwrap().public_key
+        );
+        assert_ne!(
+            recovered.wallet(DEFAULT_PATH).unwrap().public_key,
+            recovered.wallet("m/0'/0'/1'").unwrap().public_key
+        );
+    }
+
+    #[test]
+    fn test_derived_wallet_signs() {
+        let wallet = HdWallet::from_mnemonic(BIP39_VECTORS[0].0, "")
+            .unwrap()
+            .wallet(DEFAULT_PATH)
+            .unwrap();
+        let signature = wallet.sign("foo".to_owned()).unwrap();
+
+        assert!(wallet
+            .verify("foo".to_owned(), signature.serialize_compact())
+            .unwrap());
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let master = ExtendedKey::from_seed(&[0; 16]).unwrap();
+
+        for path in &["", "0/1", "m/", "m/x", "m/2147483648", "m/1''"] {
+            assert_eq!(
+                master.derive_path(path).err(),
+                Some(Error::InvalidDerivationPath),
+                "{}",
+                path
+            );
+        }
+        assert_eq!(
+            HdWallet::from_mnemonic("abandon abandon", "").err(),
+            Some(Error::InvalidMnemonic)
+        );
+    }
+}
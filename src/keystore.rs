@@ -0,0 +1,245 @@
+use crate::{address::Address, error::Error, wallet::Wallet};
+use aes_gcm::{
+    aead::{generic_array::GenericArray, Aead, NewAead},
+    Aes256Gcm,
+};
+use hmac::Hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+const KEY_FILE_VERSION: u8 = 1;
+const KDF_ROUNDS: u32 = 100_000;
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    version: u8,
+    address: Address,
+    rounds: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+pub struct Keystore {
+    dir: PathBuf,
+    rounds: u32,
+}
+
+impl Keystore {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        fs::create_dir_all(&dir)?;
+
+        Ok(Keystore {
+            dir: dir.as_ref().to_path_buf(),
+            rounds: KDF_ROUNDS,
+        })
+    }
+
+    pub fn save(&self, wallet: &Wallet, passphrase: &str) -> Result<(), Error> {
+        let salt: [u8; 16] = rand::random();
+        let nonce: [u8; 12] = rand::random();
+        let ciphertext = cipher(passphrase, &salt, self.rounds)
+            .encrypt(
+                GenericArray::from_slice(&nonce),
+                wallet.export_secret_key().as_bytes(),
+            )
+            .map_err(|_| Error::InvalidKeyFile)?;
+
+        let key_file = KeyFile {
+            version: KEY_FILE_VERSION,
+            address: wallet.address(),
+            rounds: self.rounds,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        };
+
+        let path = self.path(&wallet.address());
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&key_file).unwrap())?;
+        fs::rename(tmp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn load(&self, address: &Address, passphrase: &str) -> Result<Wallet, Error> {
+        let key_file = match fs::read(self.path(address)) {
+            Ok(bytes) => {
+                serde_json::from_slice::<KeyFile>(&bytes).map_err(|_| Error::InvalidKeyFile)?
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(Error::UnknownAddress),
+            Err(e) => return Err(e.into()),
+        };
+
+        if key_file.version != KEY_FILE_VERSION || key_file.address != *address {
+            return Err(Error::InvalidKeyFile);
+        }
+
+        let salt = hex::decode(&key_file.salt).map_err(|_| Error::InvalidKeyFile)?;
+        let nonce = hex::decode(&key_file.nonce).map_err(|_| Error::InvalidKeyFile)?;
+        let ciphertext = hex::decode(&key_file.ciphertext).map_err(|_| Error::InvalidKeyFile)?;
+
+        if nonce.len() != 12 {
+            return Err(Error::InvalidKeyFile);
+        }
+
+        let secret_key = cipher(passphrase, &salt, key_file.rounds)
+            .decrypt(GenericArray::from_slice(&nonce), &ciphertext[..])
+            .map_err(|_| Error::WrongPassphrase)?;
+        let secret_key = String::from_utf8(secret_key).map_err(|_| Error::InvalidKeyFile)?;
+        let wallet = Wallet::import_secret_key(&secret_key)?;
+
+        if wallet.address() != *address {
+            return Err(Error::InvalidKeyFile);
+        }
+
+        Ok(wallet)
+    }
+
+    pub fn addresses(&self) -> Result<Vec<Address>, Error> {
+        let mut addresses = vec![];
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+
+            if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+                let key_file: KeyFile =
+                    serde_json::from_slice(&fs::read(path)?).map_err(|_| Error::InvalidKeyFile)?;
+                addresses.push(key_file.address);
+            }
+        }
+        addresses.sort();
+
+        Ok(addresses)
+    }
+
+    pub fn import(&self, secret_key: &str, passphrase: &str) -> Result<Wallet, Error> {
+        let wallet = Wallet::import_secret_key(secret_key)?;
+        self.save(&wallet, passphrase)?;
+
+        Ok(wallet)
+    }
+
+    pub fn export(&self, address: &Address, passphrase: &str) -> Result<String, Error> {
+        Ok(self.load(address, passphrase)?.export_secret_key())
+    }
+
+    fn path(&self, address: &Address) -> PathBuf {
+        self.dir.join(format!("{}.json", address))
+    }
+}
+
+fn cipher(passphrase: &str, salt: &[u8], rounds: u32) -> Aes256Gcm {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, rounds as usize, &mut key);
+
+    Aes256Gcm::new(GenericArray::from_slice(&key))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keystore(dir: &tempfile::TempDir) -> Keystore {
+        Keystore {
+            dir: dir.path().to_path_buf(),
+            rounds: 1,
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = keystore(&dir);
+        let wallet = Wallet::new(0);
+
+        keystore.save(&wallet, "foo").unwrap();
+        let loaded = keystore.load(&wallet.address(), "foo").unwrap();
+
+        assert_eq!(loaded.address(), wallet.address());
+        assert_eq!(loaded.export_secret_key(), wallet.export_secret_key());
+        assert!(!fs::read_to_string(keystore.path(&wallet.address()))
+            .unwrap()
+            .contains(&wallet.export_secret_key()));
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = keystore(&dir);
+        let wallet = Wallet::new(0);
+
+        keystore.save(&wallet, "foo").unwrap();
+
+        assert_eq!(
+            keystore.load(&wallet.address(), "bar").err(),
+            Some(Error::WrongPassphrase)
+        );
+        assert_eq!(
+            keystore.load(&Wallet::new(0).address(), "foo").err(),
+            Some(Error::UnknownAddress)
+        );
+    }
+
+    #[test]
+    fn test_list_addresses() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = keystore(&dir);
+        let wallets = [Wallet::new(0), Wallet::new(0)];
+        let mut expected = vec![wallets[0].address(), wallets[1].address()];
+        expected.sort();
+
+        for wallet in wallets.iter() {
+            keystore.save(wallet, "foo").unwrap();
+        }
+        fs::write(dir.path().join("README"), "foo").unwrap();
+
+        assert_eq!(keystore.addresses().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_import_and_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = keystore(&dir);
+        let secret_key = hex::encode([1; 32]);
+
+        let wallet = keystore.import(&secret_key, "foo").unwrap();
+
+        assert_eq!(
+            wallet.public_key.to_string(),
+            "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f"
+        );
+        assert_eq!(
+            keystore.export(&wallet.address(), "foo").unwrap(),
+            secret_key
+        );
+        assert_eq!(
+            keystore.import("foo", "foo").err(),
+            Some(Error::InvalidSecretKey)
+        );
+    }
+
+    #[test]
+    fn test_tampered_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = keystore(&dir);
+        let wallet = Wallet::new(0);
+        let other = Wallet::new(0);
+
+        keystore.save(&other, "foo").unwrap();
+        fs::rename(
+            keystore.path(&other.address()),
+            keystore.path(&wallet.address()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            keystore.load(&wallet.address(), "foo").err(),
+            Some(Error::InvalidKeyFile)
+        );
+    }
+}
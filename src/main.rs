@@ -1,23 +1,56 @@
+mod address;
 mod block;
 mod blockchain;
+mod encoding;
+mod error;
 mod hashing;
+mod hd_wallet;
+mod keystore;
+mod p2p;
 mod pub_sub;
+mod storage;
+mod sync;
 mod transaction;
+mod transaction_miner;
+mod transaction_pool;
+mod transport;
 mod utils;
 mod wallet;
 
-use actix_web::{get, post, web, App, HttpServer, Responder};
+use actix_web::{
+    error::BlockingError, get, post, web, App, HttpResponse, HttpServer, Responder, ResponseError,
+};
+use address::Address;
 use block::Block;
 use blockchain::Blockchain;
 use futures::channel::mpsc::{channel, Receiver, Sender};
+use hd_wallet::{HdWallet, DEFAULT_PATH};
+use keystore::Keystore;
 use listenfd::ListenFd;
+use p2p::TcpTransport;
 use proptest::strategy::{Strategy, ValueTree};
 use proptest::test_runner::TestRunner;
-use pub_sub::PubSub;
+use pub_sub::{reconcile_chain, Command, PubSub};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sha2::{Digest, Sha256};
+use storage::FileStore;
+use wallet::Wallet;
 
-use std::sync::Mutex;
+use transaction::Transaction;
+use transaction_miner::TransactionMiner;
+use transaction_pool::TransactionPool;
+use transport::{InProcessNetwork, RedisTransport, Transport};
+use wallet::calculate_balance;
+
+use std::{
+    env, io,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const MAX_PAGE_SIZE: usize = 100;
 
 // #[get("/{id}/{name}/index.html")]
 // async fn index(info: web::Path<(u32, String)>) -> impl Responder {
@@ -25,14 +58,180 @@ use std::sync::Mutex;
 // }
 
 struct AppState {
-    chain_updater: Sender<String>,
+    chain_updater: Sender<Command>,
+    wallet: Wallet,
+    blockchain: Arc<Mutex<Blockchain>>,
+    transaction_pool: Arc<Mutex<TransactionPool>>,
+    miner: TransactionMiner,
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+    from: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct BlocksPage<'a> {
+    length: usize,
+    from: usize,
+    blocks: &'a [Block],
 }
 
 #[get("/api/blocks")]
-async fn index() -> impl Responder {
-    let blockchain = Blockchain::new(vec![Block::get_first_block()]);
+async fn index(state: web::Data<Mutex<AppState>>, query: web::Query<PageQuery>) -> HttpResponse {
+    let blockchain = state.lock().unwrap().blockchain.clone();
+    let blockchain = blockchain.lock().unwrap();
+    let from = query.from.unwrap_or(0);
+    let limit = query.limit.unwrap_or(MAX_PAGE_SIZE).min(MAX_PAGE_SIZE);
+
+    HttpResponse::Ok().json(BlocksPage {
+        length: blockchain.chain.len(),
+        from,
+        blocks: blockchain.page(from, limit),
+    })
+}
+
+#[get("/api/tip")]
+async fn tip(state: web::Data<Mutex<AppState>>) -> HttpResponse {
+    let blockchain = state.lock().unwrap().blockchain.clone();
+    let blockchain = blockchain.lock().unwrap();
+    let tip = blockchain.chain.last().unwrap();
+
+    HttpResponse::Ok().json(json!({
+        "height": blockchain.chain.len() - 1,
+        "hash": tip.hash,
+    }))
+}
+
+#[get("/api/blocks/{id}")]
+async fn get_block(state: web::Data<Mutex<AppState>>, id: web::Path<String>) -> HttpResponse {
+    let blockchain = state.lock().unwrap().blockchain.clone();
+    let blockchain = blockchain.lock().unwrap();
+
+    match blockchain.find_block(&id) {
+        Some(block) => HttpResponse::Ok().json(block),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+struct TransactRequest {
+    recipient: Address,
+    amount: u64,
+}
+
+#[derive(Serialize)]
+struct TransactionInfo {
+    block: Option<usize>,
+    transaction: Transaction,
+}
+
+#[post("/api/transact")]
+async fn transact(
+    state: web::Data<Mutex<AppState>>,
+    json: web::Json<TransactRequest>,
+) -> Result<HttpResponse, error::Error> {
+    if json.amount == 0 || json.amount > i64::MAX as u64 {
+        return Err(error::Error::InvalidAmount);
+    }
+
+    let mut state = state.lock().unwrap();
+    let blockchain = state.blockchain.clone();
+    let transaction_pool = state.transaction_pool.clone();
+    let blockchain = blockchain.lock().unwrap();
+    let mut transaction_pool = transaction_pool.lock().unwrap();
+
+    let address = state.wallet.address();
+    state.wallet.balance = calculate_balance(&blockchain, &address);
+
+    // A pending transaction signed over an outdated balance can never be
+    // mined, so it is replaced rather than updated.
+    let existing = transaction_pool.existing_transaction(&address).cloned();
+    let transaction = match existing {
+        Some(mut transaction) if transaction.input.1 == state.wallet.balance => {
+            transaction.update(&state.wallet, json.recipient, json.amount)?;
+
+            transaction
+        }
+        stale => {
+            if let Some(stale) = stale {
+                transaction_pool.transaction_map.remove(&stale.id);
+            }
+
+            state
+                .wallet
+                .create_transaction(json.amount, json.recipient, &blockchain)?
+        }
+    };
+    transaction_pool.set_transaction(transaction.clone());
+
+    if let Err(e) = state
+        .chain_updater
+        .try_send(Command::BroadcastTransaction(transaction.clone()))
+    {
+        println!("transaction not broadcast: {}", e);
+    }
+
+    Ok(HttpResponse::Ok().json(transaction))
+}
+
+#[get("/api/transaction-pool-map")]
+async fn transaction_pool_map(state: web::Data<Mutex<AppState>>) -> HttpResponse {
+    let transaction_pool = state.lock().unwrap().transaction_pool.clone();
+    let transaction_pool = transaction_pool.lock().unwrap();
+
+    HttpResponse::Ok().json(&transaction_pool.transaction_map)
+}
+
+#[get("/api/transactions/{id}")]
+async fn get_transaction(state: web::Data<Mutex<AppState>>, id: web::Path<String>) -> HttpResponse {
+    let (blockchain, transaction_pool) = {
+        let state = state.lock().unwrap();
+        (state.blockchain.clone(), state.transaction_pool.clone())
+    };
+
+    if let Some((height, transaction)) = blockchain.lock().unwrap().find_transaction(&id) {
+        return HttpResponse::Ok().json(TransactionInfo {
+            block: Some(height),
+            transaction,
+        });
+    }
+
+    let transaction = transaction_pool
+        .lock()
+        .unwrap()
+        .transaction_map
+        .get(id.as_str())
+        .cloned();
+
+    match transaction {
+        Some(transaction) => HttpResponse::Ok().json(TransactionInfo {
+            block: None,
+            transaction,
+        }),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[get("/api/wallet-info")]
+async fn wallet_info(state: web::Data<Mutex<AppState>>) -> HttpResponse {
+    let mut state = state.lock().unwrap();
+    let balance = calculate_balance(&state.blockchain.lock().unwrap(), &state.wallet.address());
+    state.wallet.balance = balance;
 
-    format!("{:?}", blockchain.chain)
+    HttpResponse::Ok().json(state.wallet.info())
+}
+
+#[get("/api/mine-transactions")]
+async fn mine_transactions(state: web::Data<Mutex<AppState>>) -> HttpResponse {
+    let miner = state.lock().unwrap().miner.clone();
+
+    match web::block(move || miner.mine_transactions()).await {
+        Ok(block) => HttpResponse::Ok().json(block),
+        Err(BlockingError::Error(e)) => e.error_response(),
+        Err(BlockingError::Canceled) => HttpResponse::InternalServerError().finish(),
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,7 +250,7 @@ async fn add_block(
     let mut sender = state.lock().unwrap();
     sender
         .chain_updater
-        .try_send(json.a.clone())
+        .try_send(Command::AddBlock(json.a.clone()))
         .expect("cannot send update through updater");
 
     // format!("{:?}", chain.blockchain)
@@ -60,11 +259,11 @@ async fn add_block(
 
 fn run_simulation() {
     let mut blockchain = Blockchain::new(vec![Block::get_first_block()]);
-    blockchain.add_block("bla".to_string());
+    blockchain.add_block("bla".to_string()).unwrap();
     let mut dfs = vec![];
     for i in 1..1000000 {
         let before = blockchain.chain.last().unwrap().timestamp;
-        blockchain.add_block(i.to_string());
+        blockchain.add_block(i.to_string()).unwrap();
         let after = blockchain.chain.last().unwrap().timestamp;
         dfs.push(after - before);
         println!(
@@ -76,22 +275,156 @@ fn run_simulation() {
     }
 }
 
+fn open_keystore() -> Result<(Keystore, String), error::Error> {
+    let keystore =
+        Keystore::open(env::var("MYOX_KEYSTORE").unwrap_or_else(|_| "keystore".to_string()))?;
+    let passphrase = env::var("MYOX_PASSPHRASE")
+        .ok()
+        .filter(|passphrase| !passphrase.is_empty())
+        .ok_or(error::Error::MissingPassphrase)?;
+
+    Ok((keystore, passphrase))
+}
+
+fn export_key(address: &str) -> Result<String, error::Error> {
+    let (keystore, passphrase) = open_keystore()?;
+
+    keystore.export(&Address::from_str(address)?, &passphrase)
+}
+
+fn load_wallet() -> Result<Wallet, error::Error> {
+    let (keystore, passphrase) = open_keystore()?;
+
+    if let Ok(secret_key) = env::var("MYOX_IMPORT_KEY") {
+        return keystore.import(&secret_key, &passphrase);
+    }
+
+    if let Ok(mnemonic) = env::var("MYOX_MNEMONIC") {
+        let wallet = HdWallet::from_mnemonic(&mnemonic, "")?.wallet(DEFAULT_PATH)?;
+        keystore.save(&wallet, &passphrase)?;
+
+        return Ok(wallet);
+    }
+
+    let address = match env::var("MYOX_WALLET") {
+        Ok(address) => Some(Address::from_str(&address)?),
+        Err(_) => keystore.addresses()?.into_iter().next(),
+    };
+
+    // A new wallet comes from a recovery phrase the operator passes in, so
+    // the phrase never ends up in the node's output.
+    match address {
+        Some(address) => keystore.load(&address, &passphrase),
+        None => Err(error::Error::MissingWallet),
+    }
+}
+
 #[actix_rt::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> io::Result<()> {
     // run_simulation();
 
-    let (mut pubsub, s) = PubSub::new(Blockchain::new(vec![Block::get_first_block()]))
-        .await
-        .unwrap();
+    if env::var("MYOX_NEW_MNEMONIC").is_ok() {
+        println!("{}", HdWallet::generate("").mnemonic());
+
+        return Ok(());
+    }
+
+    if let Ok(address) = env::var("MYOX_EXPORT_KEY") {
+        println!("{}", export_key(&address).map_err(io::Error::other)?);
+
+        return Ok(());
+    }
+
+    if let Ok(reward) = env::var("MYOX_MINING_REWARD") {
+        let reward = reward
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        transaction::set_mining_reward(reward).map_err(io::Error::other)?;
+    }
+
+    let wallet = load_wallet().map_err(io::Error::other)?;
+    println!("wallet address: {}", wallet.address());
+
+    let blockchain = FileStore::open(env::var("MYOX_DATA").unwrap_or_else(|_| "data".to_string()))
+        .and_then(|store| Blockchain::open(Box::new(store)))
+        .map_err(io::Error::other)?;
+    println!("loaded {} blocks", blockchain.chain.len());
+    let blockchain = Arc::new(Mutex::new(blockchain));
+
+    let transaction_pool = Arc::new(Mutex::new(TransactionPool::new()));
+
+    if let Ok(root) = env::var("MYOX_ROOT_NODE") {
+        match sync::fetch_chain(&root).await {
+            Ok(chain) => {
+                reconcile_chain(&blockchain, &transaction_pool, Blockchain::new(chain));
+            }
+            Err(e) => println!("sync with {} failed: {}", root, e),
+        }
+    }
+
+    let transport: Box<dyn Transport> = match env::var("MYOX_TRANSPORT").as_deref() {
+        Ok("local") => Box::new(InProcessNetwork::new().transport()),
+        Ok("tcp") => {
+            let seeds: Vec<String> = env::var("MYOX_SEEDS")
+                .unwrap_or_default()
+                .split(',')
+                .filter(|seed| !seed.is_empty())
+                .map(str::to_string)
+                .collect();
+            let transport = TcpTransport::start(
+                &env::var("MYOX_P2P_LISTEN").unwrap_or_else(|_| "0.0.0.0:4000".to_string()),
+                &seeds,
+            )
+            .map_err(io::Error::other)?;
+            println!("p2p listening on {}", transport.listen_addr());
+
+            Box::new(transport)
+        }
+        _ => Box::new(
+            RedisTransport::connect(
+                &env::var("MYOX_REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string()),
+            )
+            .await
+            .map_err(io::Error::other)?,
+        ),
+    };
+
+    let (mut pubsub, s) = PubSub::new(transport, blockchain.clone(), transaction_pool.clone());
+    let miner = TransactionMiner::new(
+        blockchain.clone(),
+        transaction_pool.clone(),
+        wallet.address(),
+        s.clone(),
+    );
+
+    if let Ok(interval) = env::var("MYOX_MINE_INTERVAL") {
+        let interval = interval
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        miner.clone().run(Duration::from_secs(interval));
+    }
     // pubsub.handle_message().await;
 
     let mut listenfd = ListenFd::from_env();
-    let data = web::Data::new(Mutex::new(AppState { chain_updater: s }));
+    let data = web::Data::new(Mutex::new(AppState {
+        chain_updater: s,
+        wallet,
+        blockchain,
+        transaction_pool,
+        miner,
+    }));
     let mut server = HttpServer::new(move || {
         App::new()
             .app_data(data.clone())
             .service(index)
+            .service(get_block)
+            .service(tip)
             .service(add_block)
+            .service(transact)
+            .service(transaction_pool_map)
+            .service(get_transaction)
+            .service(wallet_info)
+            .service(mine_transactions)
     });
 
     server = if let Some(l) = listenfd.take_tcp_listener(0).unwrap() {
@@ -100,7 +433,201 @@ async fn main() -> std::io::Result<()> {
         server.bind("127.0.0.1:3000")?
     };
 
-    futures::join!(server.run(), pubsub.handle_message());
+    let (server, network) = futures::join!(server.run(), pubsub.handle_message());
+    network.map_err(io::Error::other)?;
+
+    server
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{
+        http::StatusCode,
+        test::{self, TestRequest},
+    };
+    use blockchain::funded_chain;
+    use transaction::mining_reward;
+    use wallet::WalletInfo;
+
+    fn app_state(
+        wallet: &Wallet,
+        blockchain: Blockchain,
+    ) -> (web::Data<Mutex<AppState>>, Receiver<Command>) {
+        let (s, r) = channel(16);
+        let blockchain = Arc::new(Mutex::new(blockchain));
+        let transaction_pool = Arc::new(Mutex::new(TransactionPool::new()));
+        let miner = TransactionMiner::new(
+            blockchain.clone(),
+            transaction_pool.clone(),
+            wallet.address(),
+            s.clone(),
+        );
+
+        let state = web::Data::new(Mutex::new(AppState {
+            chain_updater: s,
+            wallet: wallet.clone(),
+            blockchain,
+            transaction_pool,
+            miner,
+        }));
+
+        (state, r)
+    }
+
+    fn transact_request(recipient: impl ToString, amount: u64) -> TestRequest {
+        TestRequest::post()
+            .uri("/api/transact")
+            .set_json(&json!({ "recipient": recipient.to_string(), "amount": amount }))
+    }
+
+    #[actix_rt::test]
+    async fn test_transact() {
+        let wallet = Wallet::new(0);
+        let (state, mut r) = app_state(&wallet, funded_chain(&[&wallet]));
+        let mut app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(transact)
+                .service(transaction_pool_map),
+        )
+        .await;
+        let (to, to2) = (Wallet::new(0).address(), Wallet::new(0).address());
+
+        let response = test::call_service(&mut app, transact_request(to, 11).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let created: Transaction =
+            serde_json::from_slice(&test::read_body(response).await).unwrap();
+
+        let response = test::call_service(&mut app, transact_request(to2, 5).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let updated: Transaction =
+            serde_json::from_slice(&test::read_body(response).await).unwrap();
+
+        assert_eq!(updated.id, created.id);
+        assert_eq!(updated.output_map.get(&to), Some(&11));
+        assert_eq!(updated.output_map.get(&to2), Some(&5));
+        assert_eq!(
+            updated.output_map.get(&wallet.address()),
+            Some(&(mining_reward() - 16))
+        );
+        assert_eq!(Transaction::validate_transaction(&updated), Ok(()));
+        assert!(matches!(
+            r.try_next(),
+            Ok(Some(Command::BroadcastTransaction(_)))
+        ));
+
+        let request = TestRequest::get()
+            .uri("/api/transaction-pool-map")
+            .to_request();
+        let pool: serde_json::Value = test::read_response_json(&mut app, request).await;
+        assert_eq!(pool.as_object().unwrap().len(), 1);
+        assert_eq!(pool[&updated.id]["input"], json!(updated)["input"]);
+    }
+
+    #[actix_rt::test]
+    async fn test_transact_replaces_stale_transaction() {
+        let wallet = Wallet::new(0);
+        let (state, _r) = app_state(&wallet, funded_chain(&[&wallet]));
+        let mut app =
+            test::init_service(App::new().app_data(state.clone()).service(transact)).await;
+        let to = Wallet::new(0).address();
+
+        let request = transact_request(to, 11).to_request();
+        let stale: Transaction = test::read_response_json(&mut app, request).await;
+
+        let blockchain = state.lock().unwrap().blockchain.clone();
+        blockchain
+            .lock()
+            .unwrap()
+            .add_block_with_reward(vec![], wallet.address())
+            .unwrap();
 
-    Ok(())
+        let request = transact_request(to, 5).to_request();
+        let transaction: Transaction = test::read_response_json(&mut app, request).await;
+
+        assert_ne!(transaction.id, stale.id);
+        assert_eq!(transaction.input.1, 2 * mining_reward());
+        let transaction_pool = state.lock().unwrap().transaction_pool.clone();
+        let transaction_map = &transaction_pool.lock().unwrap().transaction_map;
+        assert_eq!(transaction_map.len(), 1);
+        assert!(transaction_map.contains_key(&transaction.id));
+    }
+
+    #[actix_rt::test]
+    async fn test_transact_bad_requests() {
+        let wallet = Wallet::new(0);
+        let (state, _r) = app_state(&wallet, funded_chain(&[&wallet]));
+        let mut app =
+            test::init_service(App::new().app_data(state.clone()).service(transact)).await;
+        let to = Wallet::new(0).address();
+
+        for request in [
+            transact_request(to, 0),
+            transact_request(to, 1 << 63),
+            transact_request(to, u64::MAX),
+            transact_request(to, mining_reward() as u64 + 1),
+            transact_request("foo", 11),
+        ] {
+            let response = test::call_service(&mut app, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let transaction_pool = state.lock().unwrap().transaction_pool.clone();
+        assert!(transaction_pool.lock().unwrap().transaction_map.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_get_transaction_and_wallet_info() {
+        let mut wallet = Wallet::new(0);
+        let mut blockchain = funded_chain(&[&wallet]);
+        let mined = wallet
+            .create_transaction(11, Wallet::new(0).address(), &blockchain)
+            .unwrap();
+        blockchain
+            .add_block_with_reward(vec![mined.clone()], Wallet::new(0).address())
+            .unwrap();
+        let pending = Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap();
+
+        let (state, _r) = app_state(&wallet, blockchain);
+        let transaction_pool = state.lock().unwrap().transaction_pool.clone();
+        transaction_pool
+            .lock()
+            .unwrap()
+            .set_transaction(pending.clone());
+        let mut app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .service(get_transaction)
+                .service(wallet_info),
+        )
+        .await;
+
+        let get = |id: &str| {
+            TestRequest::get()
+                .uri(&format!("/api/transactions/{}", id))
+                .to_request()
+        };
+
+        let info: serde_json::Value = test::read_response_json(&mut app, get(&mined.id)).await;
+        assert_eq!(info["block"], 2);
+        assert_eq!(info["transaction"]["id"], mined.id);
+
+        let info: serde_json::Value = test::read_response_json(&mut app, get(&pending.id)).await;
+        assert_eq!(info["block"], serde_json::Value::Null);
+        assert_eq!(info["transaction"]["id"], pending.id);
+
+        let response = test::call_service(&mut app, get("foo")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let request = TestRequest::get().uri("/api/wallet-info").to_request();
+        let info: WalletInfo = test::read_response_json(&mut app, request).await;
+        assert_eq!(
+            info,
+            WalletInfo {
+                address: wallet.address(),
+                balance: mining_reward() - 11,
+            }
+        );
+    }
 }
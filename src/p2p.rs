@@ -0,0 +1,719 @@
+use crate::{
+    error::Error,
+    transport::{Message, Transport},
+};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedSender},
+    future::LocalBoxFuture,
+    stream::LocalBoxStream,
+    FutureExt, StreamExt,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+const PROTOCOL_VERSION: u32 = 1;
+const MAX_PEERS: usize = 16;
+const MAX_PENDING_HANDSHAKES: usize = 8;
+const MAX_FRAME_LEN: u64 = 32 * 1024 * 1024;
+const SEEN_CAPACITY: usize = 10_000;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+const OUTBOX_CAPACITY: usize = 64;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum Frame {
+    Version {
+        version: u32,
+        node_id: String,
+        listen_addr: String,
+        nonce: u64,
+    },
+    GetPeers,
+    Peers {
+        peers: Vec<String>,
+    },
+    Gossip {
+        channel: String,
+        payload: String,
+    },
+}
+
+// Frames are queued for a writer thread per peer, so a peer that stops
+// reading fills its own queue instead of blocking the sender.
+struct Peer {
+    rank: (bool, u64),
+    listen_addr: String,
+    stream: TcpStream,
+    outbox: SyncSender<Vec<u8>>,
+    awaiting_peers: AtomicBool,
+}
+
+impl Peer {
+    fn send(&self, frame: &Frame) -> io::Result<()> {
+        self.outbox
+            .try_send(encode_frame(frame))
+            .map_err(|e| match e {
+                TrySendError::Full(_) => {
+                    io::Error::new(io::ErrorKind::WouldBlock, "outbound queue full")
+                }
+                TrySendError::Disconnected(_) => {
+                    io::Error::new(io::ErrorKind::BrokenPipe, "connection closed")
+                }
+            })
+    }
+}
+
+#[derive(Default)]
+struct Seen {
+    ids: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl Seen {
+    fn insert(&mut self, id: [u8; 32]) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+
+        self.order.push_back(id);
+        if self.order.len() > SEEN_CAPACITY {
+            let oldest = self.order.pop_front().unwrap();
+            self.ids.remove(&oldest);
+        }
+
+        true
+    }
+}
+
+struct Node {
+    node_id: String,
+    listen_addr: String,
+    peers: Mutex<HashMap<String, Arc<Peer>>>,
+    dialing: Mutex<HashSet<String>>,
+    handshaking: Mutex<usize>,
+    seen: Mutex<Seen>,
+    subscribers: Mutex<Vec<UnboundedSender<Message>>>,
+}
+
+// Holds a connection slot until the handshake is over: dials count towards
+// MAX_PEERS and inbound connections towards MAX_PENDING_HANDSHAKES.
+struct Pending {
+    node: Arc<Node>,
+    dialed: Option<String>,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        match &self.dialed {
+            Some(addr) => {
+                self.node.dialing.lock().unwrap().remove(addr);
+            }
+            None => *self.node.handshaking.lock().unwrap() -= 1,
+        }
+    }
+}
+
+pub struct TcpTransport {
+    node: Arc<Node>,
+}
+
+impl TcpTransport {
+    pub fn start(listen: &str, seeds: &[String]) -> Result<Self, Error> {
+        let listener = TcpListener::bind(listen)?;
+        let node = Arc::new(Node {
+            node_id: hex::encode(rand::random::<[u8; 16]>()),
+            listen_addr: listener.local_addr()?.to_string(),
+            peers: Mutex::new(HashMap::new()),
+            dialing: Mutex::new(HashSet::new()),
+            handshaking: Mutex::new(0),
+            seen: Mutex::new(Seen::default()),
+            subscribers: Mutex::new(vec![]),
+        });
+
+        let accepting = node.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match (stream, accepting.reserve_inbound()) {
+                    (Ok(stream), Some(pending)) => {
+                        let node = accepting.clone();
+                        thread::spawn(move || node.run_peer(stream, pending));
+                    }
+                    (Ok(_), None) => println!("p2p too many pending handshakes"),
+                    (Err(e), _) => println!("p2p accept failed: {}", e),
+                }
+            }
+        });
+
+        for seed in seeds {
+            node.clone().connect(seed.clone());
+        }
+
+        Ok(TcpTransport { node })
+    }
+
+    pub fn listen_addr(&self) -> &str {
+        &self.node.listen_addr
+    }
+}
+
+impl Transport for TcpTransport {
+    fn publish(&mut self, message: Message) -> LocalBoxFuture<'_, Result<(), Error>> {
+        let (channel, payload) = (message.channel().to_string(), message.payload());
+        self.node
+            .seen
+            .lock()
+            .unwrap()
+            .insert(message_id(&channel, &payload));
+        self.node.broadcast(&Frame::Gossip { channel, payload });
+
+        async { Ok(()) }.boxed_local()
+    }
+
+    fn subscribe(&mut self) -> LocalBoxFuture<'_, Result<LocalBoxStream<'static, Message>, Error>> {
+        let (s, r) = unbounded();
+        self.node.subscribers.lock().unwrap().push(s);
+
+        async { Ok(r.boxed_local()) }.boxed_local()
+    }
+
+    fn relay(&mut self, message: Message) -> LocalBoxFuture<'_, Result<(), Error>> {
+        self.publish(message)
+    }
+}
+
+impl Node {
+    fn reserve_dial(self: &Arc<Self>, addr: &str) -> Option<Pending> {
+        let peers = self.peers.lock().unwrap();
+        let mut dialing = self.dialing.lock().unwrap();
+
+        if peers.len() + dialing.len() >= MAX_PEERS || !dialing.insert(addr.to_string()) {
+            return None;
+        }
+
+        Some(Pending {
+            node: self.clone(),
+            dialed: Some(addr.to_string()),
+        })
+    }
+
+    fn reserve_inbound(self: &Arc<Self>) -> Option<Pending> {
+        let mut handshaking = self.handshaking.lock().unwrap();
+
+        if *handshaking >= MAX_PENDING_HANDSHAKES {
+            return None;
+        }
+        *handshaking += 1;
+
+        Some(Pending {
+            node: self.clone(),
+            dialed: None,
+        })
+    }
+
+    fn connect(self: Arc<Self>, addr: String) {
+        let pending = match self.reserve_dial(&addr) {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        thread::spawn(move || {
+            let stream = addr
+                .to_socket_addrs()
+                .and_then(|mut addrs| {
+                    addrs
+                        .next()
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))
+                })
+                .and_then(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT));
+
+            match stream {
+                Ok(stream) => self.run_peer(stream, pending),
+                Err(e) => println!("p2p connect to {} failed: {}", addr, e),
+            }
+        });
+    }
+
+    fn run_peer(self: Arc<Self>, stream: TcpStream, pending: Pending) {
+        let outbound = pending.dialed.is_some();
+        let handshake = stream
+            .try_clone()
+            .map(BufReader::new)
+            .and_then(|mut reader| Ok((self.handshake(&stream, &mut reader, outbound)?, reader)));
+        let ((node_id, peer), mut reader) = match handshake {
+            Ok(handshake) => handshake,
+            Err(e) => {
+                println!("p2p handshake failed: {}", e);
+                return;
+            }
+        };
+
+        let registered = self.register(&node_id, peer.clone());
+        drop(pending);
+        if !registered {
+            return;
+        }
+        println!("p2p peer {} connected", peer.listen_addr);
+
+        peer.awaiting_peers.store(true, Ordering::SeqCst);
+        let result: io::Result<()> = peer.send(&Frame::GetPeers).and_then(|_| loop {
+            let frame = read_frame(&mut reader)?;
+            self.handle_frame(&node_id, &peer, frame)?;
+        });
+
+        if let Err(e) = result {
+            println!("p2p peer {} disconnected: {}", peer.listen_addr, e);
+        }
+        self.unregister(&node_id, &peer);
+    }
+
+    fn handshake(
+        &self,
+        stream: &TcpStream,
+        reader: &mut BufReader<TcpStream>,
+        outbound: bool,
+    ) -> io::Result<(String, Arc<Peer>)> {
+        let mut writer = stream.try_clone()?;
+        let nonce = rand::random();
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        write_frame(
+            &mut writer,
+            &Frame::Version {
+                version: PROTOCOL_VERSION,
+                node_id: self.node_id.clone(),
+                listen_addr: self.listen_addr.clone(),
+                nonce,
+            },
+        )?;
+
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let frame = read_frame(reader)?;
+        stream.set_read_timeout(None)?;
+
+        let (node_id, listen_addr, remote_nonce) = match frame {
+            Frame::Version {
+                version,
+                node_id,
+                listen_addr,
+                nonce,
+            } if version == PROTOCOL_VERSION && node_id != self.node_id => {
+                (node_id, listen_addr, nonce)
+            }
+            _ => return Err(invalid_data("unexpected version message")),
+        };
+
+        // A node listening on all interfaces is reachable at the address it
+        // connected from.
+        let listen_addr = match listen_addr.parse::<SocketAddr>() {
+            Ok(mut addr) if addr.ip().is_unspecified() => {
+                addr.set_ip(stream.peer_addr()?.ip());
+                addr.to_string()
+            }
+            _ => listen_addr,
+        };
+
+        // Both ends rank a connection the same way: the one opened by the
+        // node with the lower id wins, then the higher initiator nonce.
+        let rank = if outbound {
+            (self.node_id < node_id, nonce)
+        } else {
+            (node_id < self.node_id, remote_nonce)
+        };
+
+        let (outbox, frames) = sync_channel(OUTBOX_CAPACITY);
+        let peer = Peer {
+            rank,
+            listen_addr,
+            stream: stream.try_clone()?,
+            outbox,
+            awaiting_peers: AtomicBool::new(false),
+        };
+        thread::spawn(move || write_frames(writer, frames));
+
+        Ok((node_id, Arc::new(peer)))
+    }
+
+    fn register(&self, node_id: &str, peer: Arc<Peer>) -> bool {
+        let mut peers = self.peers.lock().unwrap();
+
+        match peers.get(node_id) {
+            Some(existing) if existing.rank >= peer.rank => false,
+            _ if peers.len() >= MAX_PEERS && !peers.contains_key(node_id) => false,
+            existing => {
+                if let Some(existing) = existing {
+                    let _ = existing.stream.shutdown(Shutdown::Both);
+                }
+                peers.insert(node_id.to_string(), peer);
+
+                true
+            }
+        }
+    }
+
+    fn unregister(&self, node_id: &str, peer: &Arc<Peer>) {
+        let mut peers = self.peers.lock().unwrap();
+
+        if matches!(peers.get(node_id), Some(existing) if Arc::ptr_eq(existing, peer)) {
+            peers.remove(node_id);
+        }
+    }
+
+    fn handle_frame(self: &Arc<Self>, node_id: &str, peer: &Peer, frame: Frame) -> io::Result<()> {
+        match frame {
+            Frame::Version { .. } => return Err(invalid_data("repeated version message")),
+            Frame::GetPeers => {
+                let peers = self
+                    .peers
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|(id, _)| id.as_str() != node_id)
+                    .map(|(_, peer)| peer.listen_addr.clone())
+                    .collect();
+                peer.send(&Frame::Peers { peers })?;
+            }
+            Frame::Peers { peers } => {
+                if !peer.awaiting_peers.swap(false, Ordering::SeqCst) {
+                    return Err(invalid_data("unsolicited peer list"));
+                }
+
+                for addr in peers.into_iter().take(MAX_PEERS) {
+                    let known = addr == self.listen_addr
+                        || self
+                            .peers
+                            .lock()
+                            .unwrap()
+                            .values()
+                            .any(|peer| peer.listen_addr == addr);
+
+                    if !known {
+                        self.clone().connect(addr);
+                    }
+                }
+            }
+            Frame::Gossip { channel, payload } => {
+                if !self
+                    .seen
+                    .lock()
+                    .unwrap()
+                    .insert(message_id(&channel, &payload))
+                {
+                    return Ok(());
+                }
+
+                match Message::decode(&channel, &payload) {
+                    Some(Ok(message)) => self
+                        .subscribers
+                        .lock()
+                        .unwrap()
+                        .retain(|subscriber| subscriber.unbounded_send(message.clone()).is_ok()),
+                    Some(Err(e)) => println!("rejected message: {}", e),
+                    None => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn broadcast(&self, frame: &Frame) {
+        let peers: Vec<Arc<Peer>> = self.peers.lock().unwrap().values().cloned().collect();
+
+        for peer in peers {
+            if let Err(e) = peer.send(frame) {
+                println!("p2p send to {} failed: {}", peer.listen_addr, e);
+                let _ = peer.stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+fn message_id(channel: &str, payload: &str) -> [u8; 32] {
+    let mut id = [0; 32];
+    id.copy_from_slice(&Sha256::digest(
+        format!("{}\n{}", channel, payload).as_bytes(),
+    ));
+
+    id
+}
+
+fn encode_frame(frame: &Frame) -> Vec<u8> {
+    let mut line = serde_json::to_vec(frame).expect("frame serialized");
+    line.push(b'\n');
+
+    line
+}
+
+fn write_frame<W: Write>(writer: &mut W, frame: &Frame) -> io::Result<()> {
+    writer.write_all(&encode_frame(frame))
+}
+
+fn write_frames(mut stream: TcpStream, frames: Receiver<Vec<u8>>) {
+    for line in frames {
+        if let Err(e) = stream.write_all(&line) {
+            println!("p2p write failed: {}", e);
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+    }
+}
+
+fn read_frame<R: BufRead>(reader: &mut R) -> io::Result<Frame> {
+    let mut line = vec![];
+    reader.take(MAX_FRAME_LEN).read_until(b'\n', &mut line)?;
+
+    if line.last() != Some(&b'\n') {
+        return Err(match line.len() as u64 {
+            0 => io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"),
+            MAX_FRAME_LEN => invalid_data("frame too long"),
+            _ => io::Error::new(io::ErrorKind::UnexpectedEof, "truncated frame"),
+        });
+    }
+
+    serde_json::from_slice(&line).map_err(|e| invalid_data(&e.to_string()))
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{block::Block, transaction::Transaction, wallet::Wallet};
+    use futures::executor::block_on;
+    use std::time::Instant;
+
+    fn wait_until<F: FnMut() -> bool>(mut condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    impl TcpTransport {
+        fn peer_count(&self) -> usize {
+            self.node.peers.lock().unwrap().len()
+        }
+    }
+
+    fn node(seeds: &[&TcpTransport]) -> TcpTransport {
+        let seeds: Vec<String> = seeds
+            .iter()
+            .map(|seed| seed.listen_addr().to_string())
+            .collect();
+
+        TcpTransport::start("127.0.0.1:0", &seeds).unwrap()
+    }
+
+    fn fake_peer(node: &TcpTransport) -> TcpStream {
+        let mut stream = TcpStream::connect(node.listen_addr()).unwrap();
+        write_frame(
+            &mut stream,
+            &Frame::Version {
+                version: PROTOCOL_VERSION,
+                node_id: "foo".to_string(),
+                listen_addr: "127.0.0.1:1".to_string(),
+                nonce: 0,
+            },
+        )
+        .unwrap();
+
+        stream
+    }
+
+    #[test]
+    fn test_peer_exchange_and_gossip() {
+        let mut a = node(&[]);
+        let b = node(&[&a]);
+        wait_until(|| b.peer_count() == 1);
+        let mut c = node(&[&b]);
+        wait_until(|| a.peer_count() == 2 && b.peer_count() == 2 && c.peer_count() == 2);
+
+        let mut messages = block_on(c.subscribe()).unwrap();
+        let transaction =
+            Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap();
+        block_on(a.publish(Message::Transaction(transaction.clone()))).unwrap();
+
+        let mut received = None;
+        wait_until(|| {
+            received = messages.next().now_or_never().flatten();
+            received.is_some()
+        });
+        match received.take() {
+            Some(Message::Transaction(received)) => assert_eq!(received.input, transaction.input),
+            _ => panic!("expected a transaction"),
+        }
+
+        block_on(a.publish(Message::Transaction(transaction))).unwrap();
+        block_on(a.publish(Message::Blockchain(vec![Block::get_first_block()]))).unwrap();
+        wait_until(|| {
+            received = messages.next().now_or_never().flatten();
+            received.is_some()
+        });
+        assert!(matches!(received, Some(Message::Blockchain(_))));
+        assert!(messages.next().now_or_never().is_none());
+    }
+
+    #[test]
+    fn test_duplicate_connections() {
+        let mut a = node(&[]);
+        let mut b = node(&[&a]);
+        for _ in 0..3 {
+            b.node.clone().connect(a.listen_addr().to_string());
+            a.node.clone().connect(b.listen_addr().to_string());
+        }
+
+        wait_until(|| a.peer_count() == 1 && b.peer_count() == 1);
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!((a.peer_count(), b.peer_count()), (1, 1));
+
+        let mut messages = block_on(b.subscribe()).unwrap();
+        block_on(a.publish(Message::Blockchain(vec![]))).unwrap();
+        wait_until(|| messages.next().now_or_never().is_some());
+    }
+
+    #[test]
+    fn test_gossip_relayed_once_accepted() {
+        let mut a = node(&[]);
+        let mut b = node(&[&a]);
+        wait_until(|| a.peer_count() == 1 && b.peer_count() == 1);
+        let mut inbound = block_on(a.subscribe()).unwrap();
+        let mut messages = block_on(b.subscribe()).unwrap();
+
+        let mut stream = fake_peer(&a);
+        let message = Message::Blockchain(vec![Block::get_first_block()]);
+        write_frame(
+            &mut stream,
+            &Frame::Gossip {
+                channel: message.channel().to_string(),
+                payload: message.payload(),
+            },
+        )
+        .unwrap();
+
+        wait_until(|| inbound.next().now_or_never().is_some());
+        thread::sleep(Duration::from_millis(200));
+        assert!(messages.next().now_or_never().is_none());
+
+        block_on(a.relay(message)).unwrap();
+        wait_until(|| messages.next().now_or_never().is_some());
+    }
+
+    #[test]
+    fn test_stalled_peer_dropped() {
+        let mut a = node(&[]);
+        let _stream = fake_peer(&a);
+        wait_until(|| a.peer_count() == 1);
+
+        let mut block = Block::get_first_block();
+        block.data = "x".repeat(64 * 1024).into();
+        let message = Message::Blockchain(vec![block]);
+        for _ in 0..1000 {
+            if a.peer_count() == 0 {
+                break;
+            }
+            block_on(a.publish(message.clone())).unwrap();
+        }
+
+        wait_until(|| a.peer_count() == 0);
+    }
+
+    #[test]
+    fn test_unsolicited_peers() {
+        let a = node(&[]);
+        let mut stream = fake_peer(&a);
+        wait_until(|| a.peer_count() == 1);
+
+        let peers = Frame::Peers { peers: vec![] };
+        write_frame(&mut stream, &peers).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(a.peer_count(), 1);
+
+        write_frame(&mut stream, &peers).unwrap();
+        wait_until(|| a.peer_count() == 0);
+    }
+
+    #[test]
+    fn test_pending_dials_limited() {
+        let a = node(&[]);
+        // Listeners that never answer keep every dial in its handshake.
+        let silent: Vec<TcpListener> = (0..MAX_PEERS + 2)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+
+        for listener in &silent {
+            let addr = listener.local_addr().unwrap().to_string();
+            a.node.clone().connect(addr.clone());
+            a.node.clone().connect(addr);
+        }
+
+        assert_eq!(a.node.dialing.lock().unwrap().len(), MAX_PEERS);
+    }
+
+    #[test]
+    fn test_pending_handshakes_limited() {
+        let a = node(&[]);
+        let silent: Vec<TcpStream> = (0..MAX_PENDING_HANDSHAKES)
+            .map(|_| TcpStream::connect(a.listen_addr()).unwrap())
+            .collect();
+        wait_until(|| *a.node.handshaking.lock().unwrap() == MAX_PENDING_HANDSHAKES);
+
+        let mut reader = BufReader::new(TcpStream::connect(a.listen_addr()).unwrap());
+        assert!(read_frame(&mut reader).is_err());
+
+        drop(silent);
+        wait_until(|| *a.node.handshaking.lock().unwrap() == 0);
+        let mut reader = BufReader::new(fake_peer(&a));
+        assert!(matches!(read_frame(&mut reader), Ok(Frame::Version { .. })));
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let a = node(&[]);
+        let mut stream = TcpStream::connect(a.listen_addr()).unwrap();
+        write_frame(
+            &mut stream,
+            &Frame::Version {
+                version: PROTOCOL_VERSION + 1,
+                node_id: "foo".to_string(),
+                listen_addr: "127.0.0.1:1".to_string(),
+                nonce: 0,
+            },
+        )
+        .unwrap();
+
+        let mut reader = BufReader::new(stream);
+        assert!(matches!(read_frame(&mut reader), Ok(Frame::Version { .. })));
+        assert!(read_frame(&mut reader).is_err());
+        assert_eq!(a.peer_count(), 0);
+    }
+
+    #[test]
+    fn test_seen_eviction() {
+        let mut seen = Seen::default();
+
+        assert!(seen.insert([0; 32]));
+        assert!(!seen.insert([0; 32]));
+        for i in 0..SEEN_CAPACITY {
+            seen.insert(message_id("foo", &i.to_string()));
+        }
+        assert!(seen.insert([0; 32]));
+        assert_eq!(seen.order.len(), SEEN_CAPACITY);
+    }
+}
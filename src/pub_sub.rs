@@ -1,79 +1,182 @@
-use crate::{block::Block, blockchain::Blockchain};
-use futures_util::StreamExt as _;
-use redis::AsyncCommands;
-use redis::{Commands, Connection, RedisResult, Value};
-
-use futures::{
-    channel::mpsc::{channel, Receiver, Sender},
-    pin_mut,
+use crate::{
+    blockchain::{Blockchain, ReplaceChainResult},
+    error::Error,
+    transaction::Transaction,
+    transaction_pool::TransactionPool,
+    transport::{Message, Transport},
+    wallet::calculate_balance,
 };
-use serde_json::json;
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures_util::StreamExt as _;
+use std::sync::{Arc, Mutex};
 
-const CHANNELS: &'static [&'static str] = &["TEST", "BLOCKCHAIN"];
+pub enum Command {
+    AddBlock(String),
+    BroadcastChain,
+    BroadcastTransaction(Transaction),
+}
 
 pub struct PubSub {
-    pub_sub_c: redis::aio::PubSub,
-    publish_c: redis::aio::Connection,
-    blockchain: Blockchain,
-    receiver: Receiver<String>,
+    transport: Box<dyn Transport>,
+    blockchain: Arc<Mutex<Blockchain>>,
+    transaction_pool: Arc<Mutex<TransactionPool>>,
+    receiver: Receiver<Command>,
 }
 
 impl PubSub {
-    pub async fn new(blockchain: Blockchain) -> redis::RedisResult<(Self, Sender<String>)> {
-        let client = redis::Client::open("redis://127.0.0.1/").unwrap();
-        let publish_conn = client.get_async_connection().await?;
-        let pubsub_conn = client.get_async_connection().await?.into_pubsub();
+    pub fn new(
+        transport: Box<dyn Transport>,
+        blockchain: Arc<Mutex<Blockchain>>,
+        transaction_pool: Arc<Mutex<TransactionPool>>,
+    ) -> (Self, Sender<Command>) {
         let (s, r) = channel(1024);
 
-        Ok((
+        (
             Self {
-                pub_sub_c: pubsub_conn,
-                publish_c: publish_conn,
+                transport,
                 blockchain,
+                transaction_pool,
                 receiver: r,
             },
             s,
-        ))
+        )
     }
 
-    pub async fn handle_message(&mut self) {
-        self.pub_sub_c.subscribe("BLOCKCHAIN").await.unwrap();
-        let mut pubsub_stream = self.pub_sub_c.on_message().fuse();
+    pub async fn handle_message(&mut self) -> Result<(), Error> {
+        let mut messages = self.transport.subscribe().await?.fuse();
 
         loop {
             futures::select! {
-                msg = pubsub_stream.next() => {
-                    let chains: Blockchain = msg.expect("valid json").get_payload().unwrap();
-
-                    println!("created blockchain: {:?}", chains);
+                msg = messages.next() => match msg {
+                    Some(message) => {
+                        if receive(&self.blockchain, &self.transaction_pool, message.clone()) {
+                            self.relay(message).await;
+                        }
+                    }
+                    None => return Err(Error::Transport("subscription closed".to_string())),
                 },
-                data = self.receiver.next() => {
-                    if let Some(s) = data {
-
-                    self.blockchain.add_block(s);
-                    println!("new chain added");
-                    println!("{:?}", self.blockchain);
-                    let s = json!(self.blockchain.chain).to_string();
-                    self.publish_c
-                        .publish::<String, String, usize>("BLOCKCHAIN".to_owned(), s).await.unwrap();
+                data = self.receiver.next() => match data {
+                    Some(command) => {
+                        let message = run_command(&self.blockchain, command);
+                        if let Err(e) = self.transport.publish(message).await {
+                            println!("publish failed: {}", e);
+                        }
                     }
-               }
+                    None => return Ok(()),
+                },
             }
         }
     }
+
+    async fn relay(&mut self, message: Message) {
+        if let Err(e) = self.transport.relay(message).await {
+            println!("relay failed: {}", e);
+        }
+    }
 }
 
-impl redis::FromRedisValue for Blockchain {
-    fn from_redis_value(v: &Value) -> RedisResult<Blockchain> {
-        match v {
-            Value::Data(msg) => {
-                let vv: Vec<Block> =
-                    serde_json::from_str(std::str::from_utf8(msg).unwrap()).unwrap();
-                Ok(Blockchain::new(vv))
+fn run_command(blockchain: &Mutex<Blockchain>, command: Command) -> Message {
+    let mut blockchain = blockchain.lock().unwrap();
+    match command {
+        Command::AddBlock(data) => match blockchain.add_block(data) {
+            Ok(()) => {
+                println!("new chain added");
+                println!("{:?}", blockchain);
             }
-            _ => todo!(),
+            Err(e) => println!("block not added: {}", e),
+        },
+        Command::BroadcastChain => {}
+        Command::BroadcastTransaction(transaction) => {
+            return Message::Transaction(transaction);
         }
     }
+
+    Message::Blockchain(blockchain.chain.clone())
+}
+
+// Returns whether the message changed the node's state and should be relayed.
+fn receive(
+    blockchain: &Mutex<Blockchain>,
+    transaction_pool: &Mutex<TransactionPool>,
+    message: Message,
+) -> bool {
+    match message {
+        Message::Blockchain(chain) => {
+            reconcile_chain(blockchain, transaction_pool, Blockchain::new(chain))
+                == ReplaceChainResult::Replaced
+        }
+        Message::Transaction(transaction) => {
+            match receive_transaction(blockchain, transaction_pool, transaction) {
+                Ok(true) => {
+                    println!("accepted transaction");
+                    true
+                }
+                Ok(false) => false,
+                Err(e) => {
+                    println!("rejected transaction: {}", e);
+                    false
+                }
+            }
+        }
+    }
+}
+
+fn receive_transaction(
+    blockchain: &Mutex<Blockchain>,
+    transaction_pool: &Mutex<TransactionPool>,
+    transaction: Transaction,
+) -> Result<bool, Error> {
+    if transaction.is_reward() {
+        return Err(Error::InvalidReward);
+    }
+
+    Transaction::validate_transaction(&transaction)?;
+    // Only transactions that could be mined right now are pooled, so unfunded
+    // senders cannot grow the pool.
+    if transaction.input.1 != calculate_balance(&blockchain.lock().unwrap(), &transaction.sender())
+    {
+        return Err(Error::StaleInput);
+    }
+    Ok(transaction_pool
+        .lock()
+        .unwrap()
+        .merge_transaction(transaction))
+}
+
+pub fn reconcile_chain(
+    blockchain: &Mutex<Blockchain>,
+    transaction_pool: &Mutex<TransactionPool>,
+    chain: Blockchain,
+) -> ReplaceChainResult {
+    let mut blockchain = blockchain.lock().unwrap();
+    let common = blockchain.common_prefix(&chain);
+    let orphaned = blockchain.chain[common..].to_vec();
+    let result = blockchain.replace_chain(&chain);
+
+    if let ReplaceChainResult::Replaced | ReplaceChainResult::StoreFailed(_) = result {
+        transaction_pool
+            .lock()
+            .unwrap()
+            .reconcile(&orphaned, &blockchain.chain, common);
+    }
+
+    match &result {
+        ReplaceChainResult::Replaced => println!(
+            "accepted chain of {} blocks, {} orphaned",
+            blockchain.chain.len(),
+            orphaned.len()
+        ),
+        ReplaceChainResult::Invalid(e) => println!("rejected chain: {}", e),
+        ReplaceChainResult::LessWork => println!("ignored chain with less work"),
+        ReplaceChainResult::TieLost => println!("ignored chain with equal work"),
+        ReplaceChainResult::StoreFailed(e) => println!(
+            "kept {} blocks of the chain after a storage failure: {}",
+            blockchain.chain.len(),
+            e
+        ),
+    }
+
+    result
 }
 
 // USEFUL
@@ -84,3 +187,169 @@ impl redis::FromRedisValue for Blockchain {
 //     // println!("got message: {:?}", msg);
 //     println!("created blockchain: {:?}", chains);
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        block::Block, blockchain::funded_chain, transport::InProcessNetwork, wallet::Wallet,
+    };
+    use futures::{executor::LocalPool, task::LocalSpawnExt};
+
+    fn node(
+        network: &InProcessNetwork,
+        pool: &LocalPool,
+    ) -> (Arc<Mutex<Blockchain>>, Sender<Command>) {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(vec![Block::get_first_block()])));
+        let (mut pubsub, s) = PubSub::new(
+            Box::new(network.transport()),
+            blockchain.clone(),
+            Arc::new(Mutex::new(TransactionPool::new())),
+        );
+        pool.spawner()
+            .spawn_local(async move {
+                pubsub.handle_message().await.unwrap();
+            })
+            .unwrap();
+
+        (blockchain, s)
+    }
+
+    #[test]
+    fn test_nodes_converge() {
+        let network = InProcessNetwork::new();
+        let mut pool = LocalPool::new();
+        let (a, mut a_commands) = node(&network, &pool);
+        let (b, _b_commands) = node(&network, &pool);
+        pool.run_until_stalled();
+
+        a_commands
+            .try_send(Command::AddBlock("foo".to_string()))
+            .unwrap();
+        pool.run_until_stalled();
+
+        assert_eq!(a.lock().unwrap().chain.len(), 2);
+        assert_eq!(b.lock().unwrap().chain, a.lock().unwrap().chain);
+    }
+
+    #[test]
+    fn test_reconcile_chain() {
+        let (mut w1, mut w2) = (Wallet::new(0), Wallet::new(0));
+        let shared = funded_chain(&[&w1, &w2]);
+
+        let tx1 = w1
+            .create_transaction(10, Wallet::new(0).address(), &shared)
+            .unwrap();
+        let tx2 = w2
+            .create_transaction(20, Wallet::new(0).address(), &shared)
+            .unwrap();
+        let stale = w2
+            .create_transaction(5, Wallet::new(0).address(), &shared)
+            .unwrap();
+
+        let mut local = Blockchain::new(shared.chain.clone());
+        local
+            .add_block_with_reward(vec![tx1.clone(), stale], Wallet::new(0).address())
+            .unwrap();
+        let mut incoming = Blockchain::new(shared.chain.clone());
+        incoming
+            .add_block_with_reward(vec![tx2.clone()], Wallet::new(0).address())
+            .unwrap();
+        incoming
+            .add_block_with_reward(vec![], Wallet::new(0).address())
+            .unwrap();
+
+        let blockchain = Mutex::new(local);
+        let mut transaction_pool = TransactionPool::new();
+        transaction_pool.set_transaction(tx2.clone());
+        let transaction_pool = Mutex::new(transaction_pool);
+
+        assert_eq!(
+            reconcile_chain(
+                &blockchain,
+                &transaction_pool,
+                Blockchain::new(shared.chain.clone())
+            ),
+            ReplaceChainResult::LessWork
+        );
+        assert_eq!(
+            reconcile_chain(
+                &blockchain,
+                &transaction_pool,
+                Blockchain::new(incoming.chain.clone())
+            ),
+            ReplaceChainResult::Replaced
+        );
+
+        assert_eq!(blockchain.lock().unwrap().chain, incoming.chain);
+        let transaction_map = &transaction_pool.lock().unwrap().transaction_map;
+        assert_eq!(transaction_map.len(), 1);
+        assert_eq!(transaction_map.get(&tx1.id).unwrap().input, tx1.input);
+    }
+
+    #[test]
+    fn test_receive_transaction() {
+        let mut f_wallet = Wallet::new(0);
+        let blockchain = funded_chain(&[&f_wallet]);
+        let tx = f_wallet
+            .create_transaction(11, Wallet::new(0).address(), &blockchain)
+            .unwrap();
+        let blockchain = Mutex::new(blockchain);
+        let transaction_pool = Mutex::new(TransactionPool::new());
+        let mut forged = Transaction::new(&f_wallet, Wallet::new(0).address(), 11).unwrap();
+        *forged.output_map.get_mut(&f_wallet.address()).unwrap() = 999;
+
+        assert!(receive(
+            &blockchain,
+            &transaction_pool,
+            Message::Transaction(tx.clone()),
+        ));
+        assert!(!receive(
+            &blockchain,
+            &transaction_pool,
+            Message::Transaction(tx.clone()),
+        ));
+        assert!(!receive(
+            &blockchain,
+            &transaction_pool,
+            Message::Transaction(forged)
+        ));
+
+        assert_eq!(
+            receive_transaction(
+                &blockchain,
+                &transaction_pool,
+                Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap()
+            ),
+            Err(Error::StaleInput)
+        );
+
+        let transaction_map = &transaction_pool.lock().unwrap().transaction_map;
+        assert_eq!(transaction_map.len(), 1);
+        assert_eq!(transaction_map.get(&tx.id).unwrap().input, tx.input);
+        assert_eq!(
+            receive_transaction(
+                &blockchain,
+                &transaction_pool,
+                Transaction::reward(f_wallet.address())
+            ),
+            Err(Error::InvalidReward)
+        );
+    }
+
+    #[test]
+    fn test_rejected_chain() {
+        let mut chain = Blockchain::new(vec![Block::get_first_block()]);
+        chain.add_block("foo").unwrap();
+        chain.chain[1].data = "bar".into();
+
+        assert_eq!(
+            reconcile_chain(
+                &Mutex::new(Blockchain::new(vec![Block::get_first_block()])),
+                &Mutex::new(TransactionPool::new()),
+                chain
+            ),
+            ReplaceChainResult::Invalid(Error::HashMismatch { index: 1 })
+        );
+    }
+}
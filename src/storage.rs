@@ -0,0 +1,369 @@
+use crate::{block::Block, error::Error, hashing::checksum};
+use std::{
+    convert::TryInto,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const LOG_FILE: &str = "blocks.log";
+const INDEX_FILE: &str = "blocks.idx";
+const RECORD_HEADER_LEN: usize = 8;
+
+pub trait BlockStore {
+    fn load(&mut self) -> Result<Vec<Block>, Error>;
+
+    fn append(&mut self, block: &Block) -> Result<(), Error>;
+
+    fn truncate(&mut self, len: usize) -> Result<(), Error>;
+}
+
+// The log is a sequence of records, each a u32 payload length, a 4-byte
+// checksum and the JSON encoded block. The index holds the u64 offset of
+// every record and is rebuilt from the log whenever the two disagree.
+pub struct FileStore {
+    log: File,
+    index: File,
+    offsets: Vec<u64>,
+    end: u64,
+}
+
+impl FileStore {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        fs::create_dir_all(&dir)?;
+
+        let open = |name| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(dir.as_ref().join(name))
+        };
+
+        Ok(FileStore {
+            log: open(LOG_FILE)?,
+            index: open(INDEX_FILE)?,
+            offsets: vec![],
+            end: 0,
+        })
+    }
+
+    fn write_index(&mut self) -> Result<(), Error> {
+        let index: Vec<u8> = self
+            .offsets
+            .iter()
+            .flat_map(|offset| offset.to_be_bytes().to_vec())
+            .collect();
+
+        self.index.set_len(0)?;
+        self.index.seek(SeekFrom::Start(0))?;
+        self.index.write_all(&index)?;
+        self.index.sync_data()?;
+
+        Ok(())
+    }
+}
+
+impl BlockStore for FileStore {
+    fn load(&mut self) -> Result<Vec<Block>, Error> {
+        let mut log = vec![];
+        self.log.seek(SeekFrom::Start(0))?;
+        self.log.read_to_end(&mut log)?;
+
+        let mut blocks = vec![];
+        let mut offsets = vec![];
+        let mut pos = 0;
+
+        while pos + RECORD_HEADER_LEN <= log.len() {
+            let len = u32::from_be_bytes(log[pos..pos + 4].try_into().unwrap()) as usize;
+            let start = pos + RECORD_HEADER_LEN;
+
+            if start + len > log.len() {
+                break;
+            }
+
+            let payload = &log[start..start + len];
+            let block = match serde_json::from_slice(payload) {
+                Ok(block) if checksum(payload) == log[pos + 4..start] => block,
+                _ if start + len == log.len() => break,
+                _ => return Err(Error::CorruptStorage { offset: pos as u64 }),
+            };
+
+            blocks.push(block);
+            offsets.push(pos as u64);
+            pos = start + len;
+        }
+
+        if pos < log.len() {
+            self.log.set_len(pos as u64)?;
+            self.log.sync_data()?;
+        }
+
+        let mut index = vec![];
+        self.index.seek(SeekFrom::Start(0))?;
+        self.index.read_to_end(&mut index)?;
+
+        self.offsets = offsets;
+        self.end = pos as u64;
+
+        if index.len() != self.offsets.len() * 8
+            || index
+                .chunks(8)
+                .zip(self.offsets.iter())
+                .any(|(entry, offset)| entry != offset.to_be_bytes())
+        {
+            self.write_index()?;
+        }
+
+        Ok(blocks)
+    }
+
+    fn append(&mut self, block: &Block) -> Result<(), Error> {
+        let payload = serde_json::to_vec(block).expect("block serialized");
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(&checksum(&payload));
+        record.extend_from_slice(&payload);
+
+        self.log.seek(SeekFrom::Start(self.end))?;
+        self.log.write_all(&record)?;
+        self.log.sync_data()?;
+
+        self.index
+            .seek(SeekFrom::Start(self.offsets.len() as u64 * 8))?;
+        self.index.write_all(&self.end.to_be_bytes())?;
+        self.index.sync_data()?;
+
+        self.offsets.push(self.end);
+        self.end += record.len() as u64;
+
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), Error> {
+        if len >= self.offsets.len() {
+            return Ok(());
+        }
+
+        self.end = self.offsets[len];
+        self.offsets.truncate(len);
+
+        self.log.set_len(self.end)?;
+        self.log.sync_data()?;
+        self.index.set_len(len as u64 * 8)?;
+        self.index.sync_data()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blockchain::{Blockchain, ReplaceChainResult};
+
+    fn blocks(n: usize) -> Vec<Block> {
+        let mut blockchain = Blockchain::new(vec![Block::get_first_block()]);
+        for i in 0..n {
+            blockchain.add_block(i.to_string()).unwrap();
+        }
+
+        blockchain.chain
+    }
+
+    fn stored(dir: &tempfile::TempDir, blocks: &[Block]) {
+        let mut store = FileStore::open(dir.path()).unwrap();
+        store.load().unwrap();
+        for block in blocks {
+            store.append(block).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_append_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = blocks(3);
+        stored(&dir, &blocks);
+
+        let mut store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(store.load().unwrap(), blocks);
+        assert_eq!(
+            fs::metadata(dir.path().join(INDEX_FILE)).unwrap().len(),
+            4 * 8
+        );
+    }
+
+    #[test]
+    fn test_torn_write_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = blocks(3);
+        stored(&dir, &blocks[..3]);
+        let log_len = fs::metadata(dir.path().join(LOG_FILE)).unwrap().len();
+
+        let mut torn = vec![];
+        let payload = serde_json::to_vec(&blocks[3]).unwrap();
+        torn.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        torn.extend_from_slice(&checksum(&payload));
+        torn.extend_from_slice(&payload[..payload.len() / 2]);
+        OpenOptions::new()
+            .append(true)
+            .open(dir.path().join(LOG_FILE))
+            .unwrap()
+            .write_all(&torn)
+            .unwrap();
+
+        let mut store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(store.load().unwrap(), &blocks[..3]);
+        assert_eq!(
+            fs::metadata(dir.path().join(LOG_FILE)).unwrap().len(),
+            log_len
+        );
+
+        store.append(&blocks[3]).unwrap();
+        assert_eq!(FileStore::open(dir.path()).unwrap().load().unwrap(), blocks);
+    }
+
+    #[test]
+    fn test_torn_last_record_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = blocks(2);
+        stored(&dir, &blocks);
+
+        let mut log = fs::read(dir.path().join(LOG_FILE)).unwrap();
+        let last = log.len() - 2;
+        log[last] ^= 0xff;
+        fs::write(dir.path().join(LOG_FILE), log).unwrap();
+
+        let mut store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(store.load().unwrap(), &blocks[..2]);
+        assert_eq!(
+            fs::metadata(dir.path().join(INDEX_FILE)).unwrap().len(),
+            2 * 8
+        );
+    }
+
+    #[test]
+    fn test_corrupt_record_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        stored(&dir, &blocks(2));
+
+        let mut log = fs::read(dir.path().join(LOG_FILE)).unwrap();
+        log[RECORD_HEADER_LEN + 1] ^= 0xff;
+        fs::write(dir.path().join(LOG_FILE), log).unwrap();
+
+        assert_eq!(
+            FileStore::open(dir.path()).unwrap().load().err(),
+            Some(Error::CorruptStorage { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn test_index_rebuilt() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = blocks(2);
+        stored(&dir, &blocks);
+        fs::write(dir.path().join(INDEX_FILE), [0; 5]).unwrap();
+
+        let mut store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(store.load().unwrap(), blocks);
+        assert_eq!(
+            fs::metadata(dir.path().join(INDEX_FILE)).unwrap().len(),
+            3 * 8
+        );
+
+        store.truncate(1).unwrap();
+        assert_eq!(
+            FileStore::open(dir.path()).unwrap().load().unwrap(),
+            &blocks[..1]
+        );
+    }
+
+    #[test]
+    fn test_blockchain_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = || Blockchain::open(Box::new(FileStore::open(dir.path()).unwrap())).unwrap();
+
+        let mut blockchain = open();
+        assert_eq!(blockchain.chain, vec![Block::get_first_block()]);
+        blockchain.add_block("foo").unwrap();
+        blockchain.add_block("bar").unwrap();
+        drop(blockchain);
+
+        let mut blockchain = open();
+        assert_eq!(blockchain.chain.len(), 3);
+
+        let mut longer = Blockchain::new(blockchain.chain[..2].to_vec());
+        for _ in 0..4 {
+            longer.add_block("baz").unwrap();
+        }
+        assert_eq!(
+            blockchain.replace_chain(&longer),
+            ReplaceChainResult::Replaced
+        );
+        drop(blockchain);
+
+        assert_eq!(open().chain, longer.chain);
+    }
+
+    struct FullStore {
+        blocks: Vec<Block>,
+        capacity: usize,
+    }
+
+    impl BlockStore for FullStore {
+        fn load(&mut self) -> Result<Vec<Block>, Error> {
+            Ok(self.blocks.clone())
+        }
+
+        fn append(&mut self, block: &Block) -> Result<(), Error> {
+            if self.blocks.len() >= self.capacity {
+                return Err(Error::Io("disk full".to_string()));
+            }
+
+            self.blocks.push(block.clone());
+            Ok(())
+        }
+
+        fn truncate(&mut self, len: usize) -> Result<(), Error> {
+            self.blocks.truncate(len);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_store_failure_reported() {
+        let mut blockchain = Blockchain::open(Box::new(FullStore {
+            blocks: vec![],
+            capacity: 2,
+        }))
+        .unwrap();
+        blockchain.add_block("foo").unwrap();
+
+        assert_eq!(
+            blockchain.add_block("bar"),
+            Err(Error::Io("disk full".to_string()))
+        );
+        assert_eq!(blockchain.chain.len(), 2);
+
+        let longer = Blockchain::new(blocks(3));
+        assert_eq!(
+            blockchain.replace_chain(&longer),
+            ReplaceChainResult::StoreFailed(Error::Io("disk full".to_string()))
+        );
+        assert_eq!(blockchain.chain, &longer.chain[..2]);
+    }
+
+    #[test]
+    fn test_invalid_stored_chain_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut blocks = blocks(2);
+        blocks.remove(1);
+        stored(&dir, &blocks);
+
+        assert_eq!(
+            Blockchain::open(Box::new(FileStore::open(dir.path()).unwrap())).err(),
+            Some(Error::BrokenLink { index: 1 })
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{block::Block, error::Error};
+use actix_web::client::Client;
+use serde::Deserialize;
+use std::future::Future;
+
+const PAGE_SIZE: usize = 100;
+const MAX_PAGE_BYTES: usize = 32 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct BlocksPage {
+    length: usize,
+    blocks: Vec<Block>,
+}
+
+pub async fn fetch_chain(root: &str) -> Result<Vec<Block>, Error> {
+    let client = Client::default();
+
+    collect_pages(|from| fetch_page(&client, root, from)).await
+}
+
+async fn fetch_page(client: &Client, root: &str, from: usize) -> Result<BlocksPage, Error> {
+    let url = format!(
+        "{}/api/blocks?from={}&limit={}",
+        root.trim_end_matches('/'),
+        from,
+        PAGE_SIZE
+    );
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| Error::Transport(e.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(Error::Transport(format!(
+            "root node responded with {}",
+            response.status()
+        )));
+    }
+
+    response
+        .json()
+        .limit(MAX_PAGE_BYTES)
+        .await
+        .map_err(|e| Error::Transport(e.to_string()))
+}
+
+async fn collect_pages<F, Fut>(mut fetch_page: F) -> Result<Vec<Block>, Error>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<BlocksPage, Error>>,
+{
+    let mut blocks: Vec<Block> = vec![];
+
+    loop {
+        let page = fetch_page(blocks.len()).await?;
+        let done = page.blocks.is_empty();
+        blocks.extend(page.blocks);
+
+        if done || blocks.len() >= page.length {
+            return Ok(blocks);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blockchain::Blockchain;
+    use futures::executor::block_on;
+
+    fn page(chain: &[Block], from: usize) -> Result<BlocksPage, Error> {
+        let to = (from + 2).min(chain.len());
+
+        Ok(BlocksPage {
+            length: chain.len(),
+            blocks: chain[from.min(to)..to].to_vec(),
+        })
+    }
+
+    #[test]
+    fn test_collect_pages() {
+        let mut blockchain = Blockchain::new(vec![Block::get_first_block()]);
+        for i in 0..4 {
+            blockchain.add_block(i.to_string()).unwrap();
+        }
+        let chain = &blockchain.chain;
+
+        let mut requests = vec![];
+        let fetched = block_on(collect_pages(|from| {
+            requests.push(from);
+            async move { page(chain, from) }
+        }));
+
+        assert_eq!(fetched, Ok(blockchain.chain.clone()));
+        assert_eq!(requests, vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn test_collect_pages_error() {
+        let chain = vec![Block::get_first_block(); 3];
+
+        let fetched = block_on(collect_pages(|from| {
+            let chain = &chain;
+            async move {
+                if from == 0 {
+                    page(chain, from)
+                } else {
+                    Err(Error::Transport("foo".to_string()))
+                }
+            }
+        }));
+
+        assert_eq!(fetched, Err(Error::Transport("foo".to_string())));
+    }
+}
@@ -1,166 +1,295 @@
 use crate::{
+    address::Address,
+    encoding,
+    error::Error,
     utils::time_now,
     wallet::{verify, Wallet},
 };
-use secp256k1::{ffi, PublicKey, Signature};
-use std::{collections::HashMap, os::raw::c_uchar};
+use secp256k1::{PublicKey, Signature};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    str::FromStr,
+    sync::atomic::{AtomicI64, Ordering},
+};
 
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use uuid::Uuid;
 
-// type o = HashMap<PublicKey, i64>;
-// #[derive(Serialize)]
-// struct FfiPublicKey([c_uchar; 64]);
-// #[derive(Serialize)]
-// struct SerPublicKey(FfiPublicKey);
+const DEFAULT_MINING_REWARD: i64 = 50;
+const SIGNING_PAYLOAD_VERSION: &str = "myox-tx-v2";
+const REWARD_INPUT_ADDRESS: &str =
+    "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+const REWARD_INPUT_SIGNATURE: [u8; 64] = [0; 64];
+
+static MINING_REWARD: AtomicI64 = AtomicI64::new(DEFAULT_MINING_REWARD);
 
-// struct output(HashMap<PublicKey, i64>);
+pub fn mining_reward() -> i64 {
+    MINING_REWARD.load(Ordering::Relaxed)
+}
 
-#[derive(Clone)]
+// Every node of a network has to use the same subsidy, otherwise they reject
+// each other's blocks.
+pub fn set_mining_reward(reward: i64) -> Result<(), Error> {
+    if reward <= 0 {
+        return Err(Error::InvalidAmount);
+    }
+
+    MINING_REWARD.store(reward, Ordering::Relaxed);
+
+    Ok(())
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Transaction {
-    id: String,
-    from: Wallet,
-    to: PublicKey,
-    amount: u64,
-    pub output_map: HashMap<PublicKey, i64>,
+    pub id: String,
+    pub output_map: BTreeMap<Address, i64>,
+    #[serde(with = "encoding::input")]
     pub input: (i64, i64, PublicKey, Signature),
 }
 
 impl Transaction {
-    pub fn new(from: Wallet, to: PublicKey, amount: u64) -> Self {
-        if from.balance < amount as i64 {
-            panic!("not enough balance");
-        }
+    pub fn new(from: &Wallet, to: Address, amount: u64) -> Result<Self, Error> {
+        let amount = i64::try_from(amount).map_err(|_| Error::InvalidAmount)?;
+        let remaining = from
+            .balance
+            .checked_sub(amount)
+            .filter(|remaining| *remaining >= 0)
+            .ok_or(Error::InsufficientBalance)?;
 
-        let uuid = Uuid::new_v4();
+        let id = format!("{}", Uuid::new_v4());
+        let timestamp = time_now();
 
-        let mut output_map: HashMap<PublicKey, i64> = HashMap::new();
-        output_map.insert(to, amount as i64);
-        output_map.insert(from.public_key, from.balance - amount as i64);
+        let mut output_map: BTreeMap<Address, i64> = BTreeMap::new();
+        output_map.insert(to, amount);
+        output_map.insert(from.address(), remaining);
 
         let input = (
-            time_now(),
+            timestamp,
             from.balance,
             from.public_key,
-            from.sign(
-                serde_json::to_string(&format!("{:?}", output_map)).expect("output map serialized"),
-            )
-            .expect("transaction signature"),
+            from.sign(Transaction::signing_payload(&id, timestamp, &output_map))?,
         );
 
-        Self {
-            id: format!("{}", uuid),
-            from,
-            to,
-            amount,
+        Ok(Self {
+            id,
             output_map,
             input,
+        })
+    }
+
+    pub fn signing_payload(
+        id: &str,
+        timestamp: i64,
+        output_map: &BTreeMap<Address, i64>,
+    ) -> String {
+        let mut outputs = output_map
+            .iter()
+            .map(|(address, amount)| (address.to_string(), *amount))
+            .collect::<Vec<(String, i64)>>();
+        outputs.sort();
+
+        json!([SIGNING_PAYLOAD_VERSION, id, timestamp, outputs]).to_string()
+    }
+
+    pub fn reward(miner: Address) -> Self {
+        let mut output_map: BTreeMap<Address, i64> = BTreeMap::new();
+        output_map.insert(miner, mining_reward());
+
+        Self {
+            id: format!("{}", Uuid::new_v4()),
+            output_map,
+            input: Transaction::reward_input(),
+        }
+    }
+
+    pub fn reward_input() -> (i64, i64, PublicKey, Signature) {
+        (
+            time_now(),
+            mining_reward(),
+            PublicKey::from_str(REWARD_INPUT_ADDRESS).expect("reward input address"),
+            Signature::from_compact(&REWARD_INPUT_SIGNATURE).expect("reward input signature"),
+        )
+    }
+
+    pub fn sender(&self) -> Address {
+        Address::from(&self.input.2)
+    }
+
+    pub fn is_reward(&self) -> bool {
+        let (_, _, address, signature) = Transaction::reward_input();
+
+        self.input.2 == address && self.input.3 == signature
+    }
+
+    pub fn validate_reward(transaction: &Transaction) -> Result<(), Error> {
+        if transaction.is_reward()
+            && transaction.input.1 == mining_reward()
+            && transaction.output_map.len() == 1
+            && transaction.output_map.values().sum::<i64>() == mining_reward()
+        {
+            Ok(())
+        } else {
+            Err(Error::InvalidReward)
         }
     }
 
-    pub fn is_valid_transaction(
+    pub fn validate_transaction(
         Transaction {
-            input: (_, initial_balance, public_key, signature),
+            id,
+            input: (timestamp, initial_balance, public_key, signature),
             output_map,
             ..
-        }: Transaction,
-    ) -> bool {
-        let output_total = output_map.values().sum::<i64>();
-
-        if initial_balance != output_total {
-            return false;
+        }: &Transaction,
+    ) -> Result<(), Error> {
+        let output_total = output_map
+            .values()
+            .try_fold(0i64, |total, amount| total.checked_add(*amount));
+
+        if output_total != Some(*initial_balance) || output_map.values().any(|amount| *amount < 0) {
+            return Err(Error::OutputMismatch);
         }
 
-        if let Ok(res) = verify(
-            serde_json::to_string(&format!("{:?}", output_map)).expect("output map serialized"),
+        match verify(
+            Transaction::signing_payload(id, *timestamp, output_map),
             signature.serialize_compact(),
-            public_key,
+            *public_key,
         ) {
-            return res;
-        } else {
-            return false;
+            Ok(true) => Ok(()),
+            _ => Err(Error::BadSignature),
         }
     }
 
-    pub fn update(&mut self, from: Wallet, to: PublicKey, amount: u64) {
-        if from.balance < amount as i64 {
-            panic!("not enough balance");
+    pub fn update(&mut self, from: &Wallet, to: Address, amount: u64) -> Result<(), Error> {
+        let amount = i64::try_from(amount).map_err(|_| Error::InvalidAmount)?;
+        let remaining = self.output_map.get(&from.address()).copied().unwrap_or(0);
+
+        if remaining < amount {
+            return Err(Error::InsufficientBalance);
         }
 
-        *self.output_map.entry(to).or_insert(0) += amount as i64;
-        *self.output_map.get_mut(&from.public_key).unwrap() -= amount as i64;
-        self.input = (
-            time_now(),
-            from.balance,
-            from.public_key,
-            from.sign(
-                serde_json::to_string(&format!("{:?}", self.output_map))
-                    .expect("output map serialized"),
-            )
-            .expect("transaction signature"),
-        )
+        let mut output_map = self.output_map.clone();
+        let received = output_map.entry(to).or_insert(0);
+        *received = received.checked_add(amount).ok_or(Error::InvalidAmount)?;
+        *output_map.get_mut(&from.address()).unwrap() -= amount;
+
+        let timestamp = time_now();
+        let signature = from.sign(Transaction::signing_payload(
+            &self.id,
+            timestamp,
+            &output_map,
+        ))?;
+
+        self.output_map = output_map;
+        self.input = (timestamp, from.balance, from.public_key, signature);
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use secp256k1::SecretKey;
 
     #[test]
     fn test_has_id() {
         let f_wallet = Wallet::new(111);
-        let to_wallet_key = Wallet::new(111).public_key;
+        let to_wallet_key = Wallet::new(111).address();
 
-        let tx = Transaction::new(f_wallet.clone(), to_wallet_key, 11);
-        assert!(tx.id.len() > 0);
+        let tx = Transaction::new(&f_wallet, to_wallet_key, 11).unwrap();
+        assert!(!tx.id.is_empty());
     }
 
     #[test]
     fn test_state_after_transaction() {
         let f_wallet = Wallet::new(111);
-        let to_wallet_key = Wallet::new(111).public_key;
+        let to_wallet_key = Wallet::new(111).address();
 
-        let tx = Transaction::new(f_wallet.clone(), to_wallet_key, 11);
-        assert_eq!(*tx.output_map.get(&f_wallet.public_key).unwrap(), 111 - 11);
+        let tx = Transaction::new(&f_wallet, to_wallet_key, 11).unwrap();
+        assert_eq!(*tx.output_map.get(&f_wallet.address()).unwrap(), 111 - 11);
         assert_eq!(*tx.output_map.get(&to_wallet_key).unwrap(), 11);
     }
 
+    #[test]
+    fn test_sender_without_secret_key() {
+        let (from, tx) = test_vector_transaction();
+        let secret_key = hex::encode([1; 32]);
+
+        assert_eq!(tx.sender(), from.address());
+        assert!(!serde_json::to_string(&tx).unwrap().contains(&secret_key));
+        assert!(!hex::encode(bincode::serialize(&tx).unwrap()).contains(&secret_key));
+    }
+
     #[test]
     fn test_transaction_valid() {
         let f_wallet = Wallet::new(111);
-        let to_wallet_key = Wallet::new(111).public_key;
+        let to_wallet_key = Wallet::new(111).address();
 
-        let tx = Transaction::new(f_wallet.clone(), to_wallet_key, 11);
-        assert_eq!(Transaction::is_valid_transaction(tx), true);
+        let tx = Transaction::new(&f_wallet, to_wallet_key, 11).unwrap();
+        assert_eq!(Transaction::validate_transaction(&tx), Ok(()));
     }
 
     #[test]
     fn test_transaction_invalid() {
         let f_wallet = Wallet::new(111);
-        let to_wallet_key = Wallet::new(111).public_key;
+        let to_wallet_key = Wallet::new(111).address();
 
-        let mut tx = Transaction::new(f_wallet.clone(), to_wallet_key, 11);
-        *tx.output_map.get_mut(&f_wallet.public_key).unwrap() = 1;
-        assert_eq!(Transaction::is_valid_transaction(tx.clone()), false);
-        *tx.output_map.get_mut(&f_wallet.public_key).unwrap() = 111 - 11;
-        assert_eq!(Transaction::is_valid_transaction(tx.clone()), true);
+        let mut tx = Transaction::new(&f_wallet, to_wallet_key, 11).unwrap();
+        *tx.output_map.get_mut(&f_wallet.address()).unwrap() = 1;
+        assert_eq!(
+            Transaction::validate_transaction(&tx),
+            Err(Error::OutputMismatch)
+        );
+        *tx.output_map.get_mut(&f_wallet.address()).unwrap() = 111 - 11;
+        assert_eq!(Transaction::validate_transaction(&tx), Ok(()));
         tx.input.3 = Wallet::new(228).sign("foo".to_owned()).unwrap();
-        assert_eq!(Transaction::is_valid_transaction(tx), false);
+        assert_eq!(
+            Transaction::validate_transaction(&tx),
+            Err(Error::BadSignature)
+        );
+    }
+
+    #[test]
+    fn test_transaction_overflowing_outputs() {
+        let f_wallet = Wallet::new(0);
+        let id = format!("{}", Uuid::new_v4());
+        let timestamp = time_now();
+
+        let mut output_map = BTreeMap::new();
+        output_map.insert(f_wallet.address(), i64::MAX);
+        output_map.insert(Wallet::new(0).address(), i64::MAX);
+        output_map.insert(Wallet::new(0).address(), 2);
+
+        let signature = f_wallet
+            .sign(Transaction::signing_payload(&id, timestamp, &output_map))
+            .unwrap();
+        let tx = Transaction {
+            id,
+            output_map,
+            input: (timestamp, 0, f_wallet.public_key, signature),
+        };
+
+        assert_eq!(
+            Transaction::validate_transaction(&tx),
+            Err(Error::OutputMismatch)
+        );
     }
 
     #[test]
     fn test_transaction_next_transaction() {
         let f_wallet = Wallet::new(111);
-        let to_wallet_key = Wallet::new(111).public_key;
+        let to_wallet_key = Wallet::new(111).address();
 
-        let mut tx = Transaction::new(f_wallet.clone(), to_wallet_key, 11);
-        assert_eq!(Transaction::is_valid_transaction(tx.clone()), true);
-        let fst_signature = tx.input.3.clone();
+        let mut tx = Transaction::new(&f_wallet, to_wallet_key, 11).unwrap();
+        assert_eq!(Transaction::validate_transaction(&tx), Ok(()));
+        let fst_signature = tx.input.3;
 
-        let to_wallet_key2 = Wallet::new(5).public_key;
-        tx.update(f_wallet.clone(), to_wallet_key2, 5);
+        let to_wallet_key2 = Wallet::new(5).address();
+        tx.update(&f_wallet, to_wallet_key2, 5).unwrap();
 
-        assert_eq!(*tx.output_map.get(&f_wallet.public_key).unwrap(), 95);
+        assert_eq!(*tx.output_map.get(&f_wallet.address()).unwrap(), 95);
         assert_eq!(*tx.output_map.get(&to_wallet_key).unwrap(), 11);
         assert_eq!(*tx.output_map.get(&to_wallet_key2).unwrap(), 5);
         assert_eq!(tx.input.1, tx.output_map.values().sum::<i64>());
@@ -170,34 +299,191 @@ mod test {
     #[test]
     fn test_transaction_next_transaction_with_same_recipient() {
         let f_wallet = Wallet::new(111);
-        let to_wallet_key = Wallet::new(111).public_key;
+        let to_wallet_key = Wallet::new(111).address();
 
-        let mut tx = Transaction::new(f_wallet.clone(), to_wallet_key, 11);
-        assert_eq!(Transaction::is_valid_transaction(tx.clone()), true);
-        tx.update(f_wallet.clone(), to_wallet_key, 5);
+        let mut tx = Transaction::new(&f_wallet, to_wallet_key, 11).unwrap();
+        assert_eq!(Transaction::validate_transaction(&tx), Ok(()));
+        tx.update(&f_wallet, to_wallet_key, 5).unwrap();
 
-        assert_eq!(*tx.output_map.get(&f_wallet.public_key).unwrap(), 95);
+        assert_eq!(*tx.output_map.get(&f_wallet.address()).unwrap(), 95);
         assert_eq!(*tx.output_map.get(&to_wallet_key).unwrap(), 16);
         assert_eq!(tx.input.1, tx.output_map.values().sum::<i64>());
     }
 
     #[test]
-    #[should_panic]
     fn test_transaction_from_lower_than_amount() {
         let f_wallet = Wallet::new(111);
-        let to_wallet_key = Wallet::new(111).public_key;
+        let to_wallet_key = Wallet::new(111).address();
 
-        Transaction::new(f_wallet.clone(), to_wallet_key, 11111);
+        assert_eq!(
+            Transaction::new(&f_wallet, to_wallet_key, 11111).err(),
+            Some(Error::InsufficientBalance)
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_transaction_update_high_amount() {
         let f_wallet = Wallet::new(111);
-        let to_wallet_key = Wallet::new(111).public_key;
+        let to_wallet_key = Wallet::new(111).address();
+
+        let mut tx = Transaction::new(&f_wallet, to_wallet_key, 11).unwrap();
+        let to_wallet_key2 = Wallet::new(5).address();
+        assert_eq!(
+            tx.update(&f_wallet, to_wallet_key2, 11111),
+            Err(Error::InsufficientBalance)
+        );
+    }
+    #[test]
+    fn test_transaction_amount_out_of_range() {
+        let f_wallet = Wallet::new(111);
+        let to_wallet_key = Wallet::new(0).address();
+
+        assert_eq!(
+            Transaction::new(&f_wallet, to_wallet_key, 1 << 63).err(),
+            Some(Error::InvalidAmount)
+        );
+
+        let mut tx = Transaction::new(&f_wallet, to_wallet_key, 11).unwrap();
+        assert_eq!(
+            tx.update(&f_wallet, to_wallet_key, u64::MAX),
+            Err(Error::InvalidAmount)
+        );
+    }
+
+    #[test]
+    fn test_reward_transaction() {
+        let miner = Wallet::new(0).address();
+        let tx = Transaction::reward(miner);
+
+        assert!(tx.is_reward());
+        assert_eq!(Transaction::validate_reward(&tx), Ok(()));
+        assert_eq!(*tx.output_map.get(&miner).unwrap(), mining_reward());
+        assert!(Transaction::validate_transaction(&tx).is_err());
+    }
+
+    #[test]
+    fn test_reward_transaction_invalid_amount() {
+        let miner = Wallet::new(0).address();
+        let mut tx = Transaction::reward(miner);
+        *tx.output_map.get_mut(&miner).unwrap() = mining_reward() + 1;
+
+        assert_eq!(Transaction::validate_reward(&tx), Err(Error::InvalidReward));
+    }
+
+    #[test]
+    fn test_regular_transaction_is_not_reward() {
+        let tx = Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap();
+
+        assert!(!tx.is_reward());
+        assert_eq!(Transaction::validate_reward(&tx), Err(Error::InvalidReward));
+    }
+
+    fn test_vector_transaction() -> (Wallet, Transaction) {
+        let from = Wallet::from_secret_key(SecretKey::from_slice(&[1; 32]).unwrap(), 111);
+        let to = Wallet::from_secret_key(SecretKey::from_slice(&[2; 32]).unwrap(), 0);
+        let id = "00000000-0000-4000-8000-000000000000".to_string();
+        let timestamp = 1_600_000_000_000;
+
+        let mut output_map = BTreeMap::new();
+        output_map.insert(to.address(), 11);
+        output_map.insert(from.address(), 100);
+
+        let signature =
+            Signature::from_compact(&hex::decode(TEST_VECTOR_SIGNATURE).unwrap()).unwrap();
 
-        let mut tx = Transaction::new(f_wallet.clone(), to_wallet_key, 11);
-        let to_wallet_key2 = Wallet::new(5).public_key;
-        tx.update(f_wallet.clone(), to_wallet_key2, 11111);
+        let tx = Transaction {
+            id,
+            output_map,
+            input: (timestamp, 111, from.public_key, signature),
+        };
+
+        (from, tx)
+    }
+
+    const TEST_VECTOR_PAYLOAD: &str = concat!(
+        r#"["myox-tx-v2","00000000-0000-4000-8000-000000000000",1600000000000,["#,
+        r#"["MJzaqUqRaWTiWibfSZRr8rSznjisRVWXD7",100],"#,
+        r#"["MVPi9WeBMAUE8itCcXxM1ZreApHsTKMbvy",11]]]"#
+    );
+    const TEST_VECTOR_SIGNATURE: &str = concat!(
+        "83c788d4797431aa4a8bec7fdbbff34f36e83adf4a83092e6f5ce906fa88d395",
+        "0dd85bc5b8fee2ef003863140dbe5ad5d516bd7df7c01cac7ae24ba3fa3b6995"
+    );
+
+    #[test]
+    fn test_signing_payload_vector() {
+        let (from, tx) = test_vector_transaction();
+        let payload = Transaction::signing_payload(&tx.id, tx.input.0, &tx.output_map);
+
+        assert_eq!(payload, TEST_VECTOR_PAYLOAD);
+        assert_eq!(
+            hex::encode(&from.sign(payload).unwrap().serialize_compact()[..]),
+            TEST_VECTOR_SIGNATURE
+        );
+        assert_eq!(Transaction::validate_transaction(&tx), Ok(()));
+    }
+
+    #[test]
+    fn test_signing_payload_covers_id_and_timestamp() {
+        let (_, tx) = test_vector_transaction();
+
+        let mut other_id = tx.clone();
+        other_id.id = "00000000-0000-4000-8000-000000000001".to_string();
+        assert_eq!(
+            Transaction::validate_transaction(&other_id),
+            Err(Error::BadSignature)
+        );
+
+        let mut other_timestamp = tx;
+        other_timestamp.input.0 += 1;
+        assert_eq!(
+            Transaction::validate_transaction(&other_timestamp),
+            Err(Error::BadSignature)
+        );
+    }
+
+    #[test]
+    fn test_signing_payload_survives_serialization() {
+        let tx = Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap();
+        let decoded: Transaction =
+            serde_json::from_str(&serde_json::to_string(&tx).unwrap()).unwrap();
+
+        assert_eq!(Transaction::validate_transaction(&decoded), Ok(()));
+    }
+
+    #[test]
+    fn test_json_representation() {
+        let (_, tx) = test_vector_transaction();
+        let json: serde_json::Value = serde_json::to_value(&tx).unwrap();
+
+        assert!(json.get("from").is_none());
+        assert!(json.get("to").is_none());
+        assert!(json.get("amount").is_none());
+        assert_eq!(
+            json["output_map"]["MJzaqUqRaWTiWibfSZRr8rSznjisRVWXD7"],
+            100
+        );
+        assert_eq!(
+            json["input"],
+            json!([
+                1_600_000_000_000i64,
+                111,
+                "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+                TEST_VECTOR_SIGNATURE
+            ])
+        );
+    }
+
+    #[test]
+    fn test_binary_representation() {
+        let (_, tx) = test_vector_transaction();
+        let bytes = bincode::serialize(&tx).unwrap();
+        let decoded: Transaction = bincode::deserialize(&bytes).unwrap();
+
+        assert_eq!(decoded.id, tx.id);
+        assert_eq!(decoded.output_map, tx.output_map);
+        assert_eq!(decoded.input, tx.input);
+        assert_eq!(Transaction::validate_transaction(&decoded), Ok(()));
+        assert!(bincode::deserialize::<Transaction>(&bytes[1..]).is_err());
     }
 }
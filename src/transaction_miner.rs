@@ -0,0 +1,133 @@
+use crate::{
+    address::Address, block::Block, blockchain::Blockchain, error::Error, pub_sub::Command,
+    transaction_pool::TransactionPool,
+};
+use futures::channel::mpsc::Sender;
+use std::{
+    slice,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+#[derive(Clone)]
+pub struct TransactionMiner {
+    blockchain: Arc<Mutex<Blockchain>>,
+    transaction_pool: Arc<Mutex<TransactionPool>>,
+    miner: Address,
+    broadcaster: Sender<Command>,
+}
+
+impl TransactionMiner {
+    pub fn new(
+        blockchain: Arc<Mutex<Blockchain>>,
+        transaction_pool: Arc<Mutex<TransactionPool>>,
+        miner: Address,
+        broadcaster: Sender<Command>,
+    ) -> Self {
+        Self {
+            blockchain,
+            transaction_pool,
+            miner,
+            broadcaster,
+        }
+    }
+
+    pub fn mine_transactions(&self) -> Result<Block, Error> {
+        let block = loop {
+            let (transactions, tip) = {
+                let blockchain = self.blockchain.lock().unwrap();
+                let mut transaction_pool = self.transaction_pool.lock().unwrap();
+                transaction_pool.clear_invalid(&blockchain.chain);
+
+                (
+                    transaction_pool.mineable_transactions(&blockchain.chain),
+                    blockchain.chain.last().unwrap().clone(),
+                )
+            };
+
+            // Mining happens without holding the chain, so a block that lost
+            // the race against another one is simply mined again on the new tip.
+            let block = Block::new_with_reward(transactions, self.miner, &tip);
+
+            let mut blockchain = self.blockchain.lock().unwrap();
+            if blockchain.chain.last().unwrap().hash != tip.hash {
+                continue;
+            }
+
+            blockchain.append(block.clone())?;
+            self.transaction_pool
+                .lock()
+                .unwrap()
+                .clear_blockchain_transactions(slice::from_ref(&block));
+
+            break block;
+        };
+
+        // A full channel already holds a pending broadcast of the chain.
+        let _ = self.broadcaster.clone().try_send(Command::BroadcastChain);
+
+        Ok(block)
+    }
+
+    pub fn run(self, interval: Duration) {
+        thread::spawn(move || loop {
+            match self.mine_transactions() {
+                Ok(block) => println!("mined block {}", block.hash),
+                Err(e) => println!("mining failed: {}", e),
+            }
+
+            thread::sleep(interval);
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        blockchain::funded_chain, transaction::mining_reward, wallet::calculate_balance,
+        wallet::Wallet,
+    };
+    use futures::{channel::mpsc::channel, StreamExt};
+
+    #[test]
+    fn test_mine_transactions() {
+        let mut f_wallet = Wallet::new(0);
+        let blockchain = funded_chain(&[&f_wallet]);
+        let to = Wallet::new(0).address();
+
+        let mut transaction_pool = TransactionPool::new();
+        let tx = f_wallet.create_transaction(11, to, &blockchain).unwrap();
+        let mut forged = f_wallet.create_transaction(11, to, &blockchain).unwrap();
+        *forged.output_map.get_mut(&to).unwrap() = 999;
+        transaction_pool.set_transaction(tx.clone());
+        transaction_pool.set_transaction(forged);
+
+        let blockchain = Arc::new(Mutex::new(blockchain));
+        let transaction_pool = Arc::new(Mutex::new(transaction_pool));
+        let (s, mut r) = channel(1);
+        let miner = TransactionMiner::new(
+            blockchain.clone(),
+            transaction_pool.clone(),
+            Wallet::new(0).address(),
+            s,
+        );
+
+        let block = miner.mine_transactions().unwrap();
+        let blockchain = blockchain.lock().unwrap();
+
+        assert_eq!(blockchain.chain.last(), Some(&block));
+        assert_eq!(Blockchain::validate_chain(&blockchain), Ok(()));
+        assert_eq!(calculate_balance(&blockchain, &to), 11);
+        assert_eq!(
+            calculate_balance(&blockchain, &miner.miner),
+            mining_reward()
+        );
+        assert!(transaction_pool.lock().unwrap().transaction_map.is_empty());
+        assert!(matches!(
+            futures::executor::block_on(r.next()),
+            Some(Command::BroadcastChain)
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+use crate::{
+    address::Address, block::Block, transaction::Transaction, wallet::calculate_balance_in,
+};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Default)]
+pub struct TransactionPool {
+    pub transaction_map: HashMap<String, Transaction>,
+}
+
+impl TransactionPool {
+    pub fn new() -> Self {
+        Self {
+            transaction_map: HashMap::new(),
+        }
+    }
+
+    pub fn set_transaction(&mut self, transaction: Transaction) {
+        self.transaction_map
+            .insert(transaction.id.clone(), transaction);
+    }
+
+    pub fn merge_transaction(&mut self, transaction: Transaction) -> bool {
+        match self.transaction_map.get(&transaction.id) {
+            Some(existing)
+                if existing.input.2 != transaction.input.2
+                    || existing.input.0 >= transaction.input.0 =>
+            {
+                false
+            }
+            _ => {
+                self.set_transaction(transaction);
+                true
+            }
+        }
+    }
+
+    pub fn existing_transaction(&self, address: &Address) -> Option<&Transaction> {
+        self.transaction_map
+            .values()
+            .find(|transaction| transaction.sender() == *address)
+    }
+
+    pub fn valid_transactions(&self) -> Vec<Transaction> {
+        self.transaction_map
+            .values()
+            .filter(|transaction| Transaction::validate_transaction(transaction).is_ok())
+            .cloned()
+            .collect()
+    }
+
+    pub fn mineable_transactions(&self, blocks: &[Block]) -> Vec<Transaction> {
+        let mut senders = HashSet::new();
+
+        self.valid_transactions()
+            .into_iter()
+            .filter(|transaction| {
+                transaction.input.1 == calculate_balance_in(blocks, &transaction.sender())
+                    && senders.insert(transaction.sender())
+            })
+            .collect()
+    }
+
+    // Drops forged transactions and those signed over a balance the sender no
+    // longer has on the chain, since neither can ever be mined.
+    pub fn clear_invalid(&mut self, blocks: &[Block]) {
+        self.transaction_map.retain(|_, transaction| {
+            Transaction::validate_transaction(transaction).is_ok()
+                && transaction.input.1 == calculate_balance_in(blocks, &transaction.sender())
+        });
+    }
+
+    pub fn reconcile(&mut self, orphaned: &[Block], chain: &[Block], common: usize) {
+        for block in orphaned {
+            for transaction in block.data.transactions().unwrap_or_default() {
+                if !transaction.is_reward() {
+                    self.set_transaction(transaction);
+                }
+            }
+        }
+
+        self.clear_blockchain_transactions(&chain[common..]);
+        self.clear_invalid(chain);
+    }
+
+    pub fn clear_blockchain_transactions(&mut self, blocks: &[Block]) {
+        for block in blocks {
+            for transaction in block.data.transactions().unwrap_or_default() {
+                self.transaction_map.remove(&transaction.id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        blockchain::{funded_chain, Blockchain},
+        wallet::Wallet,
+    };
+
+    #[test]
+    fn test_set_transaction() {
+        let mut pool = TransactionPool::new();
+        let tx = Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap();
+
+        pool.set_transaction(tx.clone());
+
+        assert_eq!(pool.transaction_map.len(), 1);
+        assert_eq!(pool.transaction_map.get(&tx.id).unwrap().input, tx.input);
+    }
+
+    #[test]
+    fn test_updated_transaction_replaces_entry() {
+        let mut pool = TransactionPool::new();
+        let f_wallet = Wallet::new(111);
+        let mut tx = Transaction::new(&f_wallet, Wallet::new(0).address(), 11).unwrap();
+        pool.set_transaction(tx.clone());
+
+        tx.update(&f_wallet, Wallet::new(0).address(), 5).unwrap();
+        pool.set_transaction(tx.clone());
+
+        assert_eq!(pool.transaction_map.len(), 1);
+        assert_eq!(pool.transaction_map.get(&tx.id).unwrap().input, tx.input);
+    }
+
+    #[test]
+    fn test_merge_keeps_latest_update() {
+        let mut pool = TransactionPool::new();
+        let f_wallet = Wallet::new(111);
+        let tx = Transaction::new(&f_wallet, Wallet::new(0).address(), 11).unwrap();
+        let mut updated = tx.clone();
+        updated
+            .update(&f_wallet, Wallet::new(0).address(), 5)
+            .unwrap();
+        updated.input.0 = tx.input.0 + 1;
+
+        assert!(pool.merge_transaction(updated.clone()));
+        assert!(!pool.merge_transaction(updated.clone()));
+        assert!(!pool.merge_transaction(tx));
+        assert_eq!(pool.transaction_map.len(), 1);
+        assert_eq!(
+            pool.transaction_map.get(&updated.id).unwrap().input,
+            updated.input
+        );
+    }
+
+    #[test]
+    fn test_merge_keeps_sender() {
+        let mut pool = TransactionPool::new();
+        let tx = Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap();
+        let mut hijack = Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap();
+        hijack.id = tx.id.clone();
+        hijack.input.0 = i64::MAX;
+        pool.set_transaction(tx.clone());
+
+        assert!(!pool.merge_transaction(hijack));
+        assert_eq!(pool.transaction_map.get(&tx.id).unwrap().input, tx.input);
+    }
+
+    #[test]
+    fn test_existing_transaction() {
+        let mut pool = TransactionPool::new();
+        let f_wallet = Wallet::new(111);
+        let tx = Transaction::new(&f_wallet, Wallet::new(0).address(), 11).unwrap();
+        pool.set_transaction(tx.clone());
+
+        assert_eq!(
+            pool.existing_transaction(&f_wallet.address()).unwrap().id,
+            tx.id
+        );
+        assert!(pool
+            .existing_transaction(&Wallet::new(0).address())
+            .is_none());
+    }
+
+    #[test]
+    fn test_clear_invalid() {
+        let mut wallets: Vec<Wallet> = (0..6).map(|_| Wallet::new(0)).collect();
+        let blockchain = funded_chain(&wallets.iter().collect::<Vec<_>>());
+        let mut pool = TransactionPool::new();
+        let mut valid = vec![];
+
+        for (i, f_wallet) in wallets.iter_mut().enumerate() {
+            let mut tx = f_wallet
+                .create_transaction(11, Wallet::new(0).address(), &blockchain)
+                .unwrap();
+
+            match i % 3 {
+                0 => *tx.output_map.get_mut(&f_wallet.address()).unwrap() = 999,
+                1 => {
+                    tx = Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap()
+                }
+                _ => valid.push(tx.id.clone()),
+            }
+
+            pool.set_transaction(tx);
+        }
+
+        assert_eq!(pool.valid_transactions().len(), 4);
+
+        pool.clear_invalid(&blockchain.chain);
+
+        assert_eq!(pool.transaction_map.len(), valid.len());
+        for id in valid {
+            assert!(pool.transaction_map.contains_key(&id));
+        }
+    }
+
+    #[test]
+    fn test_mineable_transactions() {
+        let mut f_wallet = Wallet::new(0);
+        let mut blockchain = funded_chain(&[&f_wallet]);
+        let mut pool = TransactionPool::new();
+
+        let tx = f_wallet
+            .create_transaction(11, Wallet::new(0).address(), &blockchain)
+            .unwrap();
+        let duplicate = f_wallet
+            .create_transaction(12, Wallet::new(0).address(), &blockchain)
+            .unwrap();
+        let unfunded = Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap();
+        pool.set_transaction(tx);
+        pool.set_transaction(duplicate);
+        pool.set_transaction(unfunded);
+
+        let mineable = pool.mineable_transactions(&blockchain.chain);
+        assert_eq!(mineable.len(), 1);
+        assert_eq!(mineable[0].sender(), f_wallet.address());
+
+        blockchain
+            .add_block_with_reward(mineable, Wallet::new(0).address())
+            .unwrap();
+        assert_eq!(Blockchain::validate_chain(&blockchain), Ok(()));
+
+        pool.clear_blockchain_transactions(&blockchain.chain);
+        assert_eq!(pool.transaction_map.len(), 2);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{block::Block, error::Error, transaction::Transaction};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedSender},
+    future::LocalBoxFuture,
+    stream::LocalBoxStream,
+    FutureExt, StreamExt,
+};
+use redis::AsyncCommands;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+pub const BLOCKCHAIN: &str = "BLOCKCHAIN";
+pub const TRANSACTION: &str = "TRANSACTION";
+const CHANNELS: &[&str] = &[BLOCKCHAIN, TRANSACTION];
+
+#[derive(Clone)]
+pub enum Message {
+    Blockchain(Vec<Block>),
+    Transaction(Transaction),
+}
+
+impl Message {
+    pub fn channel(&self) -> &'static str {
+        match self {
+            Message::Blockchain(_) => BLOCKCHAIN,
+            Message::Transaction(_) => TRANSACTION,
+        }
+    }
+
+    pub fn payload(&self) -> String {
+        match self {
+            Message::Blockchain(chain) => json!(chain).to_string(),
+            Message::Transaction(transaction) => json!(transaction).to_string(),
+        }
+    }
+
+    pub fn decode(channel: &str, payload: &str) -> Option<Result<Self, serde_json::Error>> {
+        match channel {
+            BLOCKCHAIN => Some(serde_json::from_str(payload).map(Message::Blockchain)),
+            TRANSACTION => Some(serde_json::from_str(payload).map(Message::Transaction)),
+            _ => None,
+        }
+    }
+}
+
+pub trait Transport {
+    fn publish(&mut self, message: Message) -> LocalBoxFuture<'_, Result<(), Error>>;
+
+    fn subscribe(&mut self) -> LocalBoxFuture<'_, Result<LocalBoxStream<'static, Message>, Error>>;
+
+    // Passes on a received message once the node accepted it. Transports that
+    // deliver every message to all nodes have nothing to do.
+    fn relay(&mut self, _message: Message) -> LocalBoxFuture<'_, Result<(), Error>> {
+        async { Ok(()) }.boxed_local()
+    }
+}
+
+pub struct RedisTransport {
+    publish_c: redis::aio::Connection,
+    pub_sub_c: Option<redis::aio::PubSub>,
+}
+
+impl RedisTransport {
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let client = redis::Client::open(url)?;
+
+        Ok(RedisTransport {
+            publish_c: client.get_async_connection().await?,
+            pub_sub_c: Some(client.get_async_connection().await?.into_pubsub()),
+        })
+    }
+}
+
+impl Transport for RedisTransport {
+    fn publish(&mut self, message: Message) -> LocalBoxFuture<'_, Result<(), Error>> {
+        async move {
+            self.publish_c
+                .publish::<&str, String, usize>(message.channel(), message.payload())
+                .await?;
+
+            Ok(())
+        }
+        .boxed_local()
+    }
+
+    fn subscribe(&mut self) -> LocalBoxFuture<'_, Result<LocalBoxStream<'static, Message>, Error>> {
+        async move {
+            let mut pub_sub_c = self
+                .pub_sub_c
+                .take()
+                .ok_or_else(|| Error::Transport("already subscribed".to_string()))?;
+            for channel in CHANNELS {
+                pub_sub_c.subscribe(*channel).await?;
+            }
+
+            // The message stream borrows the connection, so it is drained by
+            // its own task that owns the connection.
+            let (s, r) = unbounded();
+            actix_rt::spawn(async move {
+                let mut messages = pub_sub_c.on_message();
+
+                while let Some(msg) = messages.next().await {
+                    let payload = match msg.get_payload::<String>() {
+                        Ok(payload) => payload,
+                        Err(e) => {
+                            println!("rejected message: {}", e);
+                            continue;
+                        }
+                    };
+
+                    let message = match Message::decode(msg.get_channel_name(), &payload) {
+                        Some(Ok(message)) => message,
+                        Some(Err(e)) => {
+                            println!("rejected message: {}", e);
+                            continue;
+                        }
+                        None => continue,
+                    };
+
+                    if s.unbounded_send(message).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            Ok(r.boxed_local())
+        }
+        .boxed_local()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct InProcessNetwork {
+    subscribers: Arc<Mutex<Vec<UnboundedSender<Message>>>>,
+}
+
+impl InProcessNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn transport(&self) -> InProcessTransport {
+        InProcessTransport {
+            network: self.clone(),
+        }
+    }
+}
+
+pub struct InProcessTransport {
+    network: InProcessNetwork,
+}
+
+impl Transport for InProcessTransport {
+    fn publish(&mut self, message: Message) -> LocalBoxFuture<'_, Result<(), Error>> {
+        self.network
+            .subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.unbounded_send(message.clone()).is_ok());
+
+        async { Ok(()) }.boxed_local()
+    }
+
+    fn subscribe(&mut self) -> LocalBoxFuture<'_, Result<LocalBoxStream<'static, Message>, Error>> {
+        let (s, r) = unbounded();
+        self.network.subscribers.lock().unwrap().push(s);
+
+        async { Ok(r.boxed_local()) }.boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wallet::Wallet;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_in_process_delivery() {
+        let network = InProcessNetwork::new();
+        let mut a = network.transport();
+        let mut b = network.transport();
+        let mut messages = block_on(b.subscribe()).unwrap();
+
+        block_on(a.publish(Message::Blockchain(vec![Block::get_first_block()]))).unwrap();
+        drop(block_on(a.subscribe()).unwrap());
+        block_on(a.publish(Message::Blockchain(vec![]))).unwrap();
+
+        match block_on(messages.next()) {
+            Some(Message::Blockchain(chain)) => assert_eq!(chain, vec![Block::get_first_block()]),
+            _ => panic!("expected a chain"),
+        }
+        assert!(matches!(
+            block_on(messages.next()),
+            Some(Message::Blockchain(_))
+        ));
+        assert_eq!(network.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_message_encoding() {
+        let transaction =
+            Transaction::new(&Wallet::new(111), Wallet::new(0).address(), 11).unwrap();
+        let message = Message::Transaction(transaction.clone());
+
+        match Message::decode(message.channel(), &message.payload()) {
+            Some(Ok(Message::Transaction(decoded))) => assert_eq!(decoded.input, transaction.input),
+            _ => panic!("expected a transaction"),
+        }
+        assert!(matches!(Message::decode(BLOCKCHAIN, "foo"), Some(Err(_))));
+        assert!(Message::decode("TEST", "foo").is_none());
+    }
+}
@@ -1,7 +1,10 @@
 // use rand::rngs::OsRng;
-use crate::transaction::Transaction;
+use crate::{
+    address::Address, block::Block, blockchain::Blockchain, error, transaction::Transaction,
+};
 use secp256k1::rand::rngs::OsRng;
-use secp256k1::{All, Error, Message, PublicKey, Secp256k1, SecretKey, Signature, Verification};
+use secp256k1::{All, Error, Message, PublicKey, Secp256k1, SecretKey, Signature};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
 #[derive(Clone)]
@@ -12,6 +15,12 @@ pub struct Wallet {
     pub balance: i64,
 }
 
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct WalletInfo {
+    pub address: Address,
+    pub balance: i64,
+}
+
 impl Wallet {
     pub fn new(start_balance: i64) -> Self {
         let secp = Secp256k1::new();
@@ -26,8 +35,43 @@ impl Wallet {
         }
     }
 
+    pub fn from_secret_key(secret_key: SecretKey, start_balance: i64) -> Self {
+        let secp = Secp256k1::new();
+        let public_key = PublicKey::from_secret_key(&secp, &secret_key);
+
+        Wallet {
+            secp,
+            secret_key,
+            public_key,
+            balance: start_balance,
+        }
+    }
+
+    pub fn import_secret_key(secret_key: &str) -> Result<Self, error::Error> {
+        let secret_key = hex::decode(secret_key).map_err(|_| error::Error::InvalidSecretKey)?;
+        let secret_key =
+            SecretKey::from_slice(&secret_key).map_err(|_| error::Error::InvalidSecretKey)?;
+
+        Ok(Wallet::from_secret_key(secret_key, 0))
+    }
+
+    pub fn export_secret_key(&self) -> String {
+        hex::encode(&self.secret_key[..])
+    }
+
+    pub fn address(&self) -> Address {
+        Address::from(&self.public_key)
+    }
+
+    pub fn info(&self) -> WalletInfo {
+        WalletInfo {
+            address: self.address(),
+            balance: self.balance,
+        }
+    }
+
     pub fn sign(&self, data: String) -> Result<Signature, Error> {
-        let msg = Sha256::digest(&data.as_bytes());
+        let msg = Sha256::digest(data.as_bytes());
         let msg = Message::from_slice(&msg)?;
 
         // println!("long: {}", self.secp.sign(&msg, &self.secret_key));
@@ -36,35 +80,66 @@ impl Wallet {
     }
 
     pub fn verify(&self, data: String, sig: [u8; 64]) -> Result<bool, Error> {
-        let msg = Sha256::digest(&data.as_bytes());
+        let msg = Sha256::digest(data.as_bytes());
         let msg = Message::from_slice(&msg)?;
         let sig = Signature::from_compact(&sig)?;
 
         Ok(self.secp.verify(&msg, &sig, &self.public_key).is_ok())
     }
 
-    pub fn create_transaction(&mut self, amount: u64, to: PublicKey) -> Option<Transaction> {
-        if self.balance < amount as i64 {
-            None
-        } else {
-            Some(Transaction::new(self.clone(), to, amount))
-        }
+    pub fn create_transaction(
+        &mut self,
+        amount: u64,
+        to: Address,
+        chain: &Blockchain,
+    ) -> Result<Transaction, error::Error> {
+        self.balance = calculate_balance(chain, &self.address());
+
+        Transaction::new(self, to, amount)
     }
 }
 
 pub fn verify(data: String, sig: [u8; 64], public_key: PublicKey) -> Result<bool, Error> {
     let secp = Secp256k1::new();
 
-    let msg = Sha256::digest(&data.as_bytes());
+    let msg = Sha256::digest(data.as_bytes());
     let msg = Message::from_slice(&msg)?;
     let sig = Signature::from_compact(&sig)?;
 
     Ok(secp.verify(&msg, &sig, &public_key).is_ok())
 }
 
+pub fn calculate_balance(chain: &Blockchain, address: &Address) -> i64 {
+    calculate_balance_in(&chain.chain, address)
+}
+
+pub fn calculate_balance_in(blocks: &[Block], address: &Address) -> i64 {
+    let mut has_conducted_transaction = false;
+    let mut outputs_total: i64 = 0;
+
+    for block in blocks.iter().skip(1).rev() {
+        for transaction in block.data.transactions().unwrap_or_default() {
+            if transaction.sender() == *address {
+                has_conducted_transaction = true;
+            }
+
+            if let Some(amount) = transaction.output_map.get(address) {
+                outputs_total = outputs_total.saturating_add(*amount);
+            }
+        }
+
+        if has_conducted_transaction {
+            break;
+        }
+    }
+
+    outputs_total
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{blockchain::funded_chain, transaction::mining_reward};
 
     #[test]
     fn test_basic_creation() {
@@ -90,41 +165,125 @@ mod test {
         let w = Wallet::new(111);
     }
 
+    #[test]
+    fn test_info_serialized_without_secret() {
+        let w = Wallet::from_secret_key(SecretKey::from_slice(&[1; 32]).unwrap(), 111);
+        let json = serde_json::to_string(&w.info()).unwrap();
+
+        assert_eq!(
+            json,
+            r#"{"address":"MJzaqUqRaWTiWibfSZRr8rSznjisRVWXD7","balance":111}"#
+        );
+        assert_eq!(serde_json::from_str::<WalletInfo>(&json).unwrap(), w.info());
+    }
+
     #[test]
     fn transaction_not_created() {
         let mut w = Wallet::new(111);
         let to_w = Wallet::new(0);
-        let r = w.create_transaction(11111, to_w.public_key);
+        let chain = funded_chain(&[&w]);
+        let r = w.create_transaction(11111, to_w.address(), &chain);
 
-        if let Some(r) = r {
-            assert!(false);
-        } else {
-            assert!(true);
-        }
+        assert_eq!(r.err(), Some(error::Error::InsufficientBalance));
     }
 
     #[test]
     fn transaction_created() {
         let mut w = Wallet::new(111);
         let to_w = Wallet::new(0);
-        let r = w.create_transaction(1, to_w.public_key);
+        let chain = funded_chain(&[&w]);
+        let r = w.create_transaction(1, to_w.address(), &chain);
 
-        if let Some(r) = r {
-            assert!(true);
-        } else {
-            assert!(false);
-        }
+        assert!(r.is_ok());
     }
 
     #[test]
     fn transaction_has_valid_props() {
         let mut w = Wallet::new(111);
         let to_w = Wallet::new(0);
-        let r = w.create_transaction(1, to_w.public_key).unwrap();
+        let chain = funded_chain(&[&w]);
+        let r = w.create_transaction(1, to_w.address(), &chain).unwrap();
 
-        assert_eq!(r.input.1, 111);
+        assert_eq!(r.input.1, mining_reward());
         assert_eq!(r.input.2, w.public_key);
-        assert_eq!(*r.output_map.get(&to_w.public_key).unwrap(), 1);
+        assert_eq!(*r.output_map.get(&to_w.address()).unwrap(), 1);
+    }
+
+    #[test]
+    fn transaction_not_created_with_stored_balance_only() {
+        let mut w = Wallet::new(111);
+        let to_w = Wallet::new(0);
+        let chain = Blockchain::new(vec![Block::get_first_block()]);
+
+        assert_eq!(
+            w.create_transaction(1, to_w.address(), &chain).err(),
+            Some(error::Error::InsufficientBalance)
+        );
+        assert_eq!(w.balance, 0);
+    }
+
+    #[test]
+    fn balance_without_outputs() {
+        let w = Wallet::new(111);
+        let chain = Blockchain::new(vec![Block::get_first_block()]);
+
+        assert_eq!(calculate_balance(&chain, &w.address()), 0);
+    }
+
+    #[test]
+    fn balance_with_received_outputs() {
+        let w = Wallet::new(0);
+        let mut chain = funded_chain(&[&w]);
+        let miner = Wallet::new(0).address();
+        let tx1 = Transaction::new(&Wallet::new(111), w.address(), 11).unwrap();
+        let tx2 = Transaction::new(&Wallet::new(111), w.address(), 22).unwrap();
+        chain.add_block_with_reward(vec![tx1, tx2], miner).unwrap();
+
+        assert_eq!(
+            calculate_balance(&chain, &w.address()),
+            mining_reward() + 11 + 22
+        );
+    }
+
+    #[test]
+    fn balance_after_outgoing_transaction() {
+        let mut w = Wallet::new(0);
+        let mut chain = funded_chain(&[&w]);
+        let miner = Wallet::new(0).address();
+
+        let outgoing = w
+            .create_transaction(20, Wallet::new(0).address(), &chain)
+            .unwrap();
+        chain.add_block_with_reward(vec![outgoing], miner).unwrap();
+        assert_eq!(
+            calculate_balance(&chain, &w.address()),
+            mining_reward() - 20
+        );
+
+        let incoming = Transaction::new(&Wallet::new(111), w.address(), 5).unwrap();
+        chain.add_block_with_reward(vec![incoming], miner).unwrap();
+        assert_eq!(
+            calculate_balance(&chain, &w.address()),
+            mining_reward() - 20 + 5
+        );
+    }
+
+    #[test]
+    fn balance_after_outgoing_transaction_and_reward_in_same_block() {
+        let mut w = Wallet::new(0);
+        let mut chain = funded_chain(&[&w]);
+
+        let outgoing = w
+            .create_transaction(20, Wallet::new(0).address(), &chain)
+            .unwrap();
+        chain
+            .add_block_with_reward(vec![outgoing], w.address())
+            .unwrap();
+
+        assert_eq!(
+            calculate_balance(&chain, &w.address()),
+            mining_reward() - 20 + mining_reward()
+        );
     }
 }
 